@@ -0,0 +1,109 @@
+//! Video categories of a region, fetched from YouTube and cached in the working directory
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2021 Michael Kefeder
+use std::path::PathBuf;
+
+/// the parts of a YouTube video category rsytup needs
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct VideoCategory {
+    pub id: String,
+    pub title: String,
+    /// only assignable categories can be set on videos
+    pub assignable: bool,
+}
+
+fn cache_path(region: &str) -> PathBuf {
+    PathBuf::from(format!("categories-{}.json", region.to_uppercase()))
+}
+
+/// cached categories of a region, None if they were never fetched
+pub fn load_cached(region: &str) -> Option<Vec<VideoCategory>> {
+    let data = std::fs::read_to_string(cache_path(region)).ok()?;
+    serde_json::from_str(&data).ok()
+}
+
+pub fn store(region: &str, categories: &[VideoCategory]) -> anyhow::Result<()> {
+    std::fs::write(
+        cache_path(region),
+        serde_json::to_string_pretty(categories)?,
+    )?;
+    Ok(())
+}
+
+/// lowercase letters and digits only, "Science & Technology" becomes "sciencetechnology"
+fn normalize(s: &str) -> String {
+    s.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// finds category by ID, title or unique beginning of its title, e.g. 28, "Science &
+/// Technology" or science
+pub fn resolve<'a>(
+    categories: &'a [VideoCategory],
+    name_or_id: &str,
+) -> anyhow::Result<&'a VideoCategory> {
+    let name = normalize(name_or_id);
+    let found = match categories
+        .iter()
+        .find(|c| c.id == name_or_id || normalize(&c.title) == name)
+    {
+        Some(category) => category,
+        None => {
+            let matches: Vec<_> = categories
+                .iter()
+                .filter(|c| normalize(&c.title).starts_with(&name))
+                .collect();
+            match matches[..] {
+                [category] => category,
+                [] => anyhow::bail!("unknown category {:?}, see `list --categories`", name_or_id),
+                _ => anyhow::bail!(
+                    "category {:?} is ambiguous: {}",
+                    name_or_id,
+                    matches
+                        .iter()
+                        .map(|c| c.title.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            }
+        }
+    };
+    if !found.assignable {
+        anyhow::bail!("category {:?} can not be assigned to videos", found.title);
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn category(id: &str, title: &str, assignable: bool) -> VideoCategory {
+        VideoCategory {
+            id: id.to_string(),
+            title: title.to_string(),
+            assignable,
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let categories = vec![
+            category("22", "People & Blogs", true),
+            category("23", "Comedy", true),
+            category("28", "Science & Technology", true),
+            category("30", "Movies", false),
+            category("31", "Anime/Animation", false),
+            category("32", "Action/Adventure", false),
+        ];
+        assert_eq!(resolve(&categories, "science").unwrap().id, "28");
+        assert_eq!(resolve(&categories, "science-technology").unwrap().id, "28");
+        assert_eq!(resolve(&categories, "22").unwrap().id, "22");
+        assert_eq!(resolve(&categories, "Comedy").unwrap().id, "23");
+        assert!(resolve(&categories, "a").is_err());
+        assert!(resolve(&categories, "movies").is_err());
+        assert!(resolve(&categories, "sports").is_err());
+    }
+}
@@ -0,0 +1,94 @@
+//! Helpers to keep chapter timestamps in video descriptions correct
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2021 Michael Kefeder
+
+/// seconds from `[h:]m:ss` timestamp
+fn parse_timestamp(ts: &str) -> Option<u64> {
+    let parts = ts
+        .split(':')
+        .map(|p| p.parse::<u64>().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    match parts[..] {
+        [m, s] if s < 60 => Some(m * 60 + s),
+        [h, m, s] if m < 60 && s < 60 => Some(h * 3600 + m * 60 + s),
+        _ => None,
+    }
+}
+
+/// `[h:]m:ss` timestamp from seconds, same format YouTube uses
+fn format_timestamp(secs: u64) -> String {
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs % 3600 / 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+/// moves every chapter line (a line starting with a timestamp) by `offset` seconds.
+/// YouTube only recognizes chapters if the first one starts at 0:00, therefore a chapter named
+/// `intro_title` is inserted in front of the first chapter.
+pub fn shift(description: &str, offset: u64, intro_title: &str) -> String {
+    if offset == 0 {
+        return description.to_string();
+    }
+    let eol = if description.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let mut intro_inserted = false;
+    let mut shifted = String::with_capacity(description.len());
+    // lines keep their line ending, so only chapter lines change
+    for line in description.split_inclusive('\n') {
+        let content = line.trim_end_matches(['\r', '\n']);
+        let ending = &line[content.len()..];
+        let (ts, rest) = content.split_once(' ').unwrap_or((content, ""));
+        match parse_timestamp(ts) {
+            Some(secs) => {
+                if !intro_inserted {
+                    shifted.push_str(&format!("{} {}{}", format_timestamp(0), intro_title, eol));
+                    intro_inserted = true;
+                }
+                shifted.push_str(&format!(
+                    "{} {}{}",
+                    format_timestamp(secs + offset),
+                    rest,
+                    ending
+                ));
+            }
+            None => shifted.push_str(line),
+        }
+    }
+    shifted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timestamps() {
+        assert_eq!(parse_timestamp("0:00"), Some(0));
+        assert_eq!(parse_timestamp("12:34"), Some(754));
+        assert_eq!(parse_timestamp("1:02:03"), Some(3723));
+        assert_eq!(parse_timestamp("1:60"), None);
+        assert_eq!(parse_timestamp("Intro"), None);
+        assert_eq!(format_timestamp(754), "12:34");
+        assert_eq!(format_timestamp(3723), "1:02:03");
+    }
+
+    #[test]
+    fn test_shift() {
+        let desc = "about traits\n\n0:00 Start\n9:55 Generics\nbye";
+        assert_eq!(
+            shift(desc, 7, "Intro"),
+            "about traits\n\n0:00 Intro\n0:07 Start\n10:02 Generics\nbye"
+        );
+        assert_eq!(shift(desc, 0, "Intro"), desc);
+        let desc = "about traits\r\n\r\n0:00 Start\r\nbye\r\n";
+        assert_eq!(
+            shift(desc, 7, "Intro"),
+            "about traits\r\n\r\n0:00 Intro\r\n0:07 Start\r\nbye\r\n"
+        );
+    }
+}
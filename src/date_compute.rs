@@ -1,7 +1,10 @@
 //! Collection of helper functions to do necessary date computation
 // SPDX-License-Identifier: GPL-3.0-or-later
 // Copyright © 2021 Michael Kefeder
-use chrono::{Datelike, NaiveDate, NaiveDateTime, Weekday};
+use chrono::{
+    DateTime, Datelike, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc, Weekday,
+};
+use chrono_tz::Tz;
 
 /// NaiveDate from ISO 8601 string
 pub fn parse_iso_date(d: &str) -> Result<NaiveDate, chrono::ParseError> {
@@ -14,7 +17,43 @@ pub fn parse_iso_datetime(d: &str) -> Result<NaiveDateTime, chrono::ParseError>
     if d.len() == 10 {
         d.push_str(" 00:00:00");
     }
-    NaiveDateTime::parse_from_str(&d, "%Y-%m-%d %H:%M:%S")
+    // date and time are separated by `T` or a space
+    NaiveDateTime::parse_from_str(&d, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(&d, "%Y-%m-%d %H:%M:%S"))
+}
+
+/// NaiveTime from ISO 8601 string
+pub fn parse_iso_time(t: &str) -> Result<NaiveTime, chrono::ParseError> {
+    NaiveTime::parse_from_str(t, "%H:%M:%S")
+}
+
+/// local date of a RFC 3339 timestamp as returned by the YouTube API
+pub fn parse_rfc3339_date(d: &str, tz: &Tz) -> Result<NaiveDate, chrono::ParseError> {
+    Ok(DateTime::parse_from_rfc3339(d)?
+        .with_timezone(tz)
+        .naive_local()
+        .date())
+}
+
+/// current date in given time zone
+pub fn today(tz: &Tz) -> NaiveDate {
+    Utc::now().with_timezone(tz).naive_local().date()
+}
+
+/// converts local wall-clock time of given time zone to UTC.
+/// when clocks are turned back the earlier of the two instants is used, times skipped when
+/// clocks are turned forward are moved forward by the gap.
+pub fn to_utc(local: NaiveDateTime, tz: &Tz) -> DateTime<Utc> {
+    match tz.from_local_datetime(&local) {
+        LocalResult::Single(dt) => dt.with_timezone(&Utc),
+        LocalResult::Ambiguous(earliest, _) => earliest.with_timezone(&Utc),
+        LocalResult::None => to_utc(local + chrono::Duration::hours(1), tz),
+    }
+}
+
+/// RFC 3339 timestamp in UTC as expected by the YouTube API
+pub fn format_rfc3339(dt: DateTime<Utc>) -> String {
+    dt.format("%Y-%m-%dT%H:%M:%SZ").to_string()
 }
 
 /// computes the next upcoming requested weekday from start-date
@@ -31,7 +70,7 @@ pub fn coming_weekday(start: NaiveDate, weekday: Weekday) -> NaiveDate {
 }
 
 /// Weeks after start-date
-pub fn add_weeks(start: NaiveDate, weeks: u8) -> NaiveDate {
+pub fn add_weeks(start: NaiveDate, weeks: u32) -> NaiveDate {
     start + chrono::Duration::weeks(weeks as i64)
 }
 
@@ -56,10 +95,30 @@ mod tests {
         assert_eq!(thu_p1w, parse_iso_date("2021-09-09").unwrap());
     }
 
+    #[test]
+    fn test_to_utc() {
+        let vienna: Tz = "Europe/Vienna".parse().unwrap();
+        let utc = |d| format_rfc3339(to_utc(parse_iso_datetime(d).unwrap(), &vienna));
+        assert_eq!(utc("2021-01-15 08:00:00"), "2021-01-15T07:00:00Z");
+        assert_eq!(utc("2021-07-15 08:00:00"), "2021-07-15T06:00:00Z");
+        // clocks turned forward at 02:00, 02:30 does not exist
+        assert_eq!(utc("2021-03-28 02:30:00"), "2021-03-28T01:30:00Z");
+        // clocks turned back at 03:00, 02:30 happens twice
+        assert_eq!(utc("2021-10-31 02:30:00"), "2021-10-31T00:30:00Z");
+    }
+
+    #[test]
+    fn test_parse_rfc3339_date() {
+        let vienna: Tz = "Europe/Vienna".parse().unwrap();
+        let d = parse_rfc3339_date("2021-09-02T23:30:00Z", &vienna).unwrap();
+        assert_eq!(d, parse_iso_date("2021-09-03").unwrap());
+    }
+
     #[test]
     fn test_parse_wo_time() {
         let thu1 = parse_iso_datetime("2021-09-02").unwrap();
         let thu2 = parse_iso_datetime("2021-09-02 00:00:00").unwrap();
+        assert_eq!(parse_iso_datetime("2021-09-02T00:00:00").unwrap(), thu2);
         assert_eq!(thu1, thu2);
     }
 }
@@ -0,0 +1,79 @@
+//! Find and replace in video descriptions with a line diff for review
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2021 Michael Kefeder
+use regex::Regex;
+
+/// replaces all matches of `re`, `replacement` may use `$1` or `${name}` for groups.
+/// returns None if the description doesn't change
+pub fn replace(description: &str, re: &Regex, replacement: &str) -> Option<String> {
+    let new = re.replace_all(description, replacement);
+    if new == description {
+        None
+    } else {
+        Some(new.into_owned())
+    }
+}
+
+/// changed lines between two texts, removed lines start with `-`, added ones with `+`
+pub fn diff_lines(old: &str, new: &str) -> Vec<String> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    // longest common subsequence table, lcs[i][j] is the length for old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut lines = vec![];
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            lines.push(format!("-{}", old[i]));
+            i += 1;
+        } else {
+            lines.push(format!("+{}", new[j]));
+            j += 1;
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replace() {
+        let re = Regex::new(r"https://discord\.gg/\w+").unwrap();
+        let desc = "about traits\njoin https://discord.gg/old123 now";
+        assert_eq!(
+            replace(desc, &re, "https://discord.gg/new456").unwrap(),
+            "about traits\njoin https://discord.gg/new456 now"
+        );
+        assert_eq!(replace("no links here", &re, "x"), None);
+        let re = Regex::new(r"sponsor: (?P<name>\w+)").unwrap();
+        assert_eq!(
+            replace("sponsor: acme", &re, "thanks to ${name}").unwrap(),
+            "thanks to acme"
+        );
+    }
+
+    #[test]
+    fn test_diff_lines() {
+        let old = "about traits\njoin old\nbye";
+        let new = "about traits\njoin new\nsee you\nbye";
+        assert_eq!(
+            diff_lines(old, new),
+            vec!["-join old", "+join new", "+see you"]
+        );
+        assert!(diff_lines(old, old).is_empty());
+    }
+}
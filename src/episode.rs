@@ -0,0 +1,99 @@
+//! Find the episode number in video titles
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2021 Michael Kefeder
+use regex::Regex;
+
+/// season and number of an episode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Episode {
+    pub season: Option<u32>,
+    pub number: u32,
+}
+
+/// how to find the episode number in a title, format <scheme>[=<regex>]
+#[derive(Debug, Clone)]
+pub enum EpisodeScheme {
+    /// leading hexadecimal number, e.g. `1F. Traits`
+    HexPrefix,
+    /// leading decimal number, e.g. `31. Traits`
+    DecimalPrefix,
+    /// season and episode anywhere in the title, e.g. `Rust S02E13 Traits`
+    SeasonEpisode,
+    /// regex, the decimal number is taken from the group named `ep` or the first group,
+    /// an optional group named `season` holds the season
+    Regex(Regex),
+    /// like regex, but the episode number is hexadecimal
+    RegexHex(Regex),
+}
+
+impl std::str::FromStr for EpisodeScheme {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (k, v) = s.split_once('=').unwrap_or((s, ""));
+        match k {
+            "hex-prefix" => Ok(EpisodeScheme::HexPrefix),
+            "decimal-prefix" => Ok(EpisodeScheme::DecimalPrefix),
+            "season-episode" => Ok(EpisodeScheme::SeasonEpisode),
+            "regex" => Ok(EpisodeScheme::Regex(Regex::new(v)?)),
+            "regex-hex" => Ok(EpisodeScheme::RegexHex(Regex::new(v)?)),
+            _ => anyhow::bail!(
+                "unknown episode scheme {:?}, use hex-prefix, decimal-prefix, season-episode, regex=<regex> or regex-hex=<regex>",
+                k
+            ),
+        }
+    }
+}
+
+impl EpisodeScheme {
+    /// episode found in title
+    pub fn extract(&self, title: &str) -> anyhow::Result<Episode> {
+        let (re, radix) = match self {
+            EpisodeScheme::HexPrefix => (Regex::new(r"^(?P<ep>[0-9A-Fa-f]+)[.\s-]")?, 16),
+            EpisodeScheme::DecimalPrefix => (Regex::new(r"^(?P<ep>\d+)[.\s-]")?, 10),
+            EpisodeScheme::SeasonEpisode => {
+                (Regex::new(r"(?i)\bS(?P<season>\d+)E(?P<ep>\d+)")?, 10)
+            }
+            EpisodeScheme::Regex(re) => (re.clone(), 10),
+            EpisodeScheme::RegexHex(re) => (re.clone(), 16),
+        };
+        let caps = re
+            .captures(title)
+            .ok_or_else(|| anyhow::anyhow!("no episode number found in {:?}", title))?;
+        let number = caps
+            .name("ep")
+            .or_else(|| caps.get(1))
+            .ok_or_else(|| anyhow::anyhow!("episode regex {:?} needs a capture group", re))?;
+        let season = match caps.name("season") {
+            Some(season) => Some(season.as_str().parse()?),
+            None => None,
+        };
+        Ok(Episode {
+            season,
+            number: u32::from_str_radix(number.as_str(), radix)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extract(scheme: &str, title: &str) -> Option<Episode> {
+        scheme.parse::<EpisodeScheme>().unwrap().extract(title).ok()
+    }
+
+    #[test]
+    fn test_extract() {
+        let ep = |season, number| Some(Episode { season, number });
+        assert_eq!(extract("hex-prefix", "1F. Traits"), ep(None, 31));
+        assert_eq!(extract("hex-prefix", "12A. Traits"), ep(None, 298));
+        assert_eq!(extract("decimal-prefix", "300. Traits"), ep(None, 300));
+        assert_eq!(extract("decimal-prefix", "Traits"), None);
+        assert_eq!(
+            extract("season-episode", "Rust s02e13 Traits"),
+            ep(Some(2), 13)
+        );
+        assert_eq!(extract(r"regex=#(\d+)$", "Traits #1024"), ep(None, 1024));
+    }
+}
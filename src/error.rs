@@ -0,0 +1,129 @@
+//! Error kinds reaching main, each exits with its own code so scripts can tell them apart
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2021 Michael Kefeder
+use crate::youtube::retry::api_failure;
+
+/// failures rsytup reports, most are added as context to the underlying error
+#[derive(Debug, Clone, thiserror::Error)]
+pub(crate) enum Error {
+    /// client secret, token cache or the OAuth flow failed
+    #[error("authorization failed: {0}")]
+    Auth(String),
+    /// the YouTube API refused a request or answered unexpectedly
+    #[error("YouTube API error {reason}: {message}")]
+    Api { reason: String, message: String },
+    /// the quota budget of the day doesn't allow the command
+    #[error("{0}")]
+    Quota(String),
+    /// ffmpeg or ffprobe failed or couldn't be run
+    #[error("{0}")]
+    Media(String),
+    #[error("thumbnail couldn't be created: {0}")]
+    Thumbnail(String),
+    /// invalid or missing options, environment variables and files
+    #[error("{0}")]
+    Config(String),
+    /// no publish date could be computed
+    #[error("{0}")]
+    Schedule(String),
+}
+
+impl Error {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Config(_) => 3,
+            Error::Auth(_) => 4,
+            Error::Api { .. } if self.is_quota_exceeded() => 6,
+            Error::Api { .. } => 5,
+            Error::Quota(_) => 6,
+            Error::Media(_) => 7,
+            Error::Thumbnail(_) => 8,
+            Error::Schedule(_) => 9,
+        }
+    }
+
+    /// the API refused the request because the daily quota is used up, or the budget is
+    pub fn is_quota_exceeded(&self) -> bool {
+        match self {
+            Error::Api { reason, .. } => {
+                reason == "quotaExceeded" || reason == "dailyLimitExceeded"
+            }
+            Error::Quota(_) => true,
+            _ => false,
+        }
+    }
+
+    /// a video which doesn't exist or isn't owned by the channel
+    pub fn video_not_found(video_id: &str) -> Self {
+        Error::Api {
+            reason: "videoNotFound".to_string(),
+            message: format!("video {} not found", video_id),
+        }
+    }
+
+    /// a response lacking a field rsytup needs
+    pub fn invalid_response(message: &str) -> Self {
+        Error::Api {
+            reason: "invalidResponse".to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+/// the kind of an error, failed API requests and token refreshes are recognized as well
+pub(crate) fn kind(err: &anyhow::Error) -> Option<Error> {
+    let known = err
+        .downcast_ref::<Error>()
+        .or_else(|| err.chain().find_map(|e| e.downcast_ref::<Error>()));
+    if let Some(known) = known {
+        return Some(known.clone());
+    }
+    if let Some(failure) = api_failure(err) {
+        return Some(Error::Api {
+            reason: failure.reason,
+            message: failure.message,
+        });
+    }
+    err.chain()
+        .find_map(|e| e.downcast_ref::<async_google_apis_common::yup_oauth2::Error>())
+        .map(|e| Error::Auth(e.to_string()))
+}
+
+/// exit code of main, 1 for errors of no particular kind. 2 is taken by invalid arguments
+pub(crate) fn exit_code(err: &anyhow::Error) -> i32 {
+    kind(err).map_or(1, |kind| kind.exit_code())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_exit_code() {
+        let err = anyhow::Error::from(Error::Media("ffmpeg not found".to_string()))
+            .context("upload failed");
+        assert_eq!(exit_code(&err), 7);
+        let err = Err::<(), _>(anyhow::anyhow!("invalid date"))
+            .context(Error::Schedule("no publish date".to_string()))
+            .unwrap_err()
+            .context("upload failed");
+        assert_eq!(exit_code(&err), 9);
+        assert_eq!(
+            format!("{:#}", err),
+            "upload failed: no publish date: invalid date"
+        );
+
+        let body = r#"{"error": {"errors": [{"reason": "quotaExceeded"}], "message": "over"}}"#;
+        let err = anyhow::Error::from(async_google_apis_common::ApiError::HTTPResponseError(
+            hyper::StatusCode::FORBIDDEN,
+            body.to_string(),
+        ));
+        assert_eq!(exit_code(&err), 6);
+        assert_eq!(
+            exit_code(&anyhow::Error::from(Error::video_not_found("abc"))),
+            5
+        );
+        assert_eq!(exit_code(&anyhow::anyhow!("something else")), 1);
+    }
+}
@@ -1,16 +1,27 @@
 //! ffmpeg helper functions
 // SPDX-License-Identifier: GPL-3.0-or-later
 // Copyright © 2021 Michael Kefeder
+use crate::error::Error;
 use std::io::{self, Write};
 use std::process::Command;
 
+/// error of a program which couldn't be started, e.g. ffmpeg isn't installed
+fn not_run(bin: impl AsRef<std::ffi::OsStr>) -> impl FnOnce(io::Error) -> anyhow::Error {
+    let bin = bin.as_ref().to_string_lossy().into_owned();
+    move |e| Error::Media(format!("{} couldn't be run: {}", bin, e)).into()
+}
+
+fn failed(message: impl Into<String>) -> anyhow::Error {
+    Error::Media(message.into()).into()
+}
+
 /// makes a screenshot of the video with the same same name ending in png
 /// returns filename of screenshot.
 pub fn bg_from_video(
     ffmpeg_bin: impl AsRef<std::ffi::OsStr>,
     video_fn: impl AsRef<std::path::Path>,
     at_second: usize,
-) -> std::path::PathBuf {
+) -> anyhow::Result<std::path::PathBuf> {
     let video_fn = std::path::PathBuf::from(video_fn.as_ref());
     let mut screenshot_fn = video_fn.clone();
     screenshot_fn.set_extension("png");
@@ -27,15 +38,293 @@ pub fn bg_from_video(
                 &screenshot_fn.to_string_lossy(),
             ])
             .output()
-            .expect("failed to execute ffmpeg");
-        io::stdout().write_all(&output.stdout).unwrap();
-        io::stderr().write_all(&output.stderr).unwrap();
+            .map_err(not_run(&ffmpeg_bin))?;
+        io::stdout().write_all(&output.stdout)?;
+        io::stderr().write_all(&output.stderr)?;
         // TODO: check for "Output file is empty, nothing was encoded" error
         // problem: status is successful in this case!!
         // most stable solution is to verify change-date for screenshot_fn?
-        assert!(output.status.success());
+        if !output.status.success() {
+            return Err(failed(format!(
+                "ffmpeg screenshot of {:?} failed",
+                video_fn
+            )));
+        }
     } else {
         println!("screenshot file exists, skipping {:?}", screenshot_fn);
     }
-    screenshot_fn
+    Ok(screenshot_fn)
+}
+
+/// target values for the EBU R128 `loudnorm` filter
+#[derive(Debug, Clone, Copy)]
+pub struct LoudnormTarget {
+    /// integrated loudness in LUFS
+    pub integrated: f32,
+    /// maximum true peak in dBTP
+    pub true_peak: f32,
+    /// loudness range in LU
+    pub lra: f32,
+}
+
+/// loudness values measured by the first `loudnorm` pass, ffmpeg reports them as strings
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct LoudnessStats {
+    pub input_i: String,
+    pub input_tp: String,
+    pub input_lra: String,
+    pub input_thresh: String,
+    pub target_offset: String,
+}
+
+impl std::fmt::Display for LoudnessStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "integrated: {} LUFS, true peak: {} dBTP, LRA: {} LU",
+            self.input_i, self.input_tp, self.input_lra
+        )
+    }
+}
+
+/// first `loudnorm` pass, analyzes the audio stream without writing any output.
+/// ffmpeg prints the measurement as JSON block at the end of stderr.
+pub fn measure_loudness(
+    ffmpeg_bin: impl AsRef<std::ffi::OsStr>,
+    video_fn: impl AsRef<std::path::Path>,
+    target: &LoudnormTarget,
+) -> anyhow::Result<LoudnessStats> {
+    let filter = format!(
+        "loudnorm=I={}:TP={}:LRA={}:print_format=json",
+        target.integrated, target.true_peak, target.lra
+    );
+    let output = Command::new(&ffmpeg_bin)
+        .args(&[
+            "-hide_banner",
+            "-nostats",
+            "-i",
+            &video_fn.as_ref().to_string_lossy(),
+            "-vn",
+            "-af",
+            &filter,
+            "-f",
+            "null",
+            "-",
+        ])
+        .output()
+        .map_err(not_run(&ffmpeg_bin))?;
+    if !output.status.success() {
+        io::stderr().write_all(&output.stderr)?;
+        return Err(failed("ffmpeg loudness measurement failed"));
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let json = match (stderr.rfind('{'), stderr.rfind('}')) {
+        (Some(start), Some(end)) if start < end => &stderr[start..=end],
+        _ => return Err(failed("no loudnorm measurement found in ffmpeg output")),
+    };
+    Ok(serde_json::from_str(json)?)
+}
+
+/// second `loudnorm` pass, writes a copy with normalized audio next to the video, named
+/// `<video>.loudnorm.<ext>`. The video stream is copied as is.
+/// returns filename of the normalized video.
+pub fn normalize_loudness(
+    ffmpeg_bin: impl AsRef<std::ffi::OsStr>,
+    video_fn: impl AsRef<std::path::Path>,
+    target: &LoudnormTarget,
+    measured: &LoudnessStats,
+) -> anyhow::Result<std::path::PathBuf> {
+    let video_fn = video_fn.as_ref();
+    let mut normalized_fn = video_fn.to_path_buf();
+    let ext = video_fn
+        .extension()
+        .map(|e| e.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "mov".to_string());
+    normalized_fn.set_extension(format!("loudnorm.{}", ext));
+    let filter = format!(
+        "loudnorm=I={}:TP={}:LRA={}:measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:offset={}:linear=true:print_format=summary",
+        target.integrated,
+        target.true_peak,
+        target.lra,
+        measured.input_i,
+        measured.input_tp,
+        measured.input_lra,
+        measured.input_thresh,
+        measured.target_offset
+    );
+    let output = Command::new(&ffmpeg_bin)
+        .args(&[
+            "-hide_banner",
+            "-i",
+            &video_fn.to_string_lossy(),
+            "-c:v",
+            "copy",
+            "-af",
+            &filter,
+            // loudnorm upsamples to 192kHz internally
+            "-ar",
+            "48000",
+            "-c:a",
+            "aac",
+            "-b:a",
+            "320k",
+            "-y",
+            &normalized_fn.to_string_lossy(),
+        ])
+        .output()
+        .map_err(not_run(&ffmpeg_bin))?;
+    io::stderr().write_all(&output.stderr)?;
+    if !output.status.success() {
+        return Err(failed("ffmpeg loudness normalization failed"));
+    }
+    Ok(normalized_fn)
+}
+
+/// runs ffprobe and returns its stdout, entries are printed one per line without keys
+fn probe(
+    ffprobe_bin: impl AsRef<std::ffi::OsStr>,
+    video_fn: &std::path::Path,
+    entries: &str,
+) -> anyhow::Result<String> {
+    let output = Command::new(&ffprobe_bin)
+        .args(&[
+            "-v",
+            "error",
+            "-show_entries",
+            entries,
+            "-of",
+            "csv=p=0",
+            &video_fn.to_string_lossy(),
+        ])
+        .output()
+        .map_err(not_run(&ffprobe_bin))?;
+    if !output.status.success() {
+        io::stderr().write_all(&output.stderr)?;
+        return Err(failed(format!("ffprobe failed for {:?}", video_fn)));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// duration of a media file in seconds
+pub fn duration(
+    ffprobe_bin: impl AsRef<std::ffi::OsStr>,
+    video_fn: impl AsRef<std::path::Path>,
+) -> anyhow::Result<f64> {
+    let d = probe(ffprobe_bin, video_fn.as_ref(), "format=duration")?;
+    d.parse().map_err(|_| {
+        failed(format!(
+            "unexpected duration {:?} of {:?}",
+            d,
+            video_fn.as_ref()
+        ))
+    })
+}
+
+/// codec parameters of all streams, clips with equal signatures can be joined without
+/// re-encoding
+fn stream_signature(
+    ffprobe_bin: impl AsRef<std::ffi::OsStr>,
+    video_fn: &std::path::Path,
+) -> anyhow::Result<String> {
+    probe(
+        ffprobe_bin,
+        video_fn,
+        "stream=codec_type,codec_name,profile,width,height,pix_fmt,r_frame_rate,sample_rate,channels",
+    )
+}
+
+/// joins the clips in given order into `<video>.concat.<ext>` next to the first non-intro clip.
+/// the concat demuxer copies streams if all clips share the same codecs, otherwise everything
+/// is re-encoded using the concat filter (which requires equal resolutions).
+/// returns filename of the joined video.
+pub fn concat(
+    ffmpeg_bin: impl AsRef<std::ffi::OsStr>,
+    ffprobe_bin: impl AsRef<std::ffi::OsStr>,
+    video_fn: impl AsRef<std::path::Path>,
+    intro: Option<&std::path::Path>,
+    outro: Option<&std::path::Path>,
+) -> anyhow::Result<std::path::PathBuf> {
+    let video_fn = video_fn.as_ref();
+    let clips: Vec<&std::path::Path> = intro
+        .into_iter()
+        .chain(std::iter::once(video_fn))
+        .chain(outro)
+        .collect();
+    let mut concat_fn = video_fn.to_path_buf();
+    let ext = video_fn
+        .extension()
+        .map(|e| e.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "mov".to_string());
+    concat_fn.set_extension(format!("concat.{}", ext));
+
+    let signature = stream_signature(&ffprobe_bin, video_fn)?;
+    let mut same_codecs = true;
+    for clip in &clips {
+        if stream_signature(&ffprobe_bin, clip)? != signature {
+            println!("codecs of {:?} differ, re-encoding", clip);
+            same_codecs = false;
+        }
+    }
+
+    let mut args: Vec<String> = vec!["-hide_banner".into()];
+    let mut list_fn = None;
+    if same_codecs {
+        let mut list = String::new();
+        for clip in &clips {
+            let path = std::fs::canonicalize(clip)?;
+            list.push_str(&format!(
+                "file '{}'\n",
+                path.to_string_lossy().replace('\'', r"'\''")
+            ));
+        }
+        let mut path = concat_fn.clone();
+        path.set_extension("txt");
+        std::fs::write(&path, list)?;
+        args.extend(["-f", "concat", "-safe", "0", "-i"].map(String::from));
+        args.push(path.to_string_lossy().into_owned());
+        args.extend(["-c", "copy"].map(String::from));
+        list_fn = Some(path);
+    } else {
+        let mut filter = String::new();
+        for (i, clip) in clips.iter().enumerate() {
+            args.push("-i".into());
+            args.push(clip.to_string_lossy().into_owned());
+            filter.push_str(&format!("[{i}:v:0][{i}:a:0]"));
+        }
+        filter.push_str(&format!("concat=n={}:v=1:a=1[v][a]", clips.len()));
+        args.extend(
+            [
+                "-filter_complex",
+                &filter,
+                "-map",
+                "[v]",
+                "-map",
+                "[a]",
+                "-c:v",
+                "libx264",
+                "-crf",
+                "18",
+                "-c:a",
+                "aac",
+                "-b:a",
+                "320k",
+            ]
+            .map(String::from),
+        );
+    }
+    args.push("-y".into());
+    args.push(concat_fn.to_string_lossy().into_owned());
+
+    let output = Command::new(&ffmpeg_bin)
+        .args(&args)
+        .output()
+        .map_err(not_run(&ffmpeg_bin))?;
+    if let Some(list_fn) = list_fn {
+        std::fs::remove_file(list_fn)?;
+    }
+    io::stderr().write_all(&output.stderr)?;
+    if !output.status.success() {
+        return Err(failed("ffmpeg concatenation failed"));
+    }
+    Ok(concat_fn)
 }
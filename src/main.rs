@@ -5,35 +5,118 @@
 use clap::Parser;
 use std::path::PathBuf;
 
+mod categories;
+mod chapters;
 mod date_compute;
+mod description;
+mod episode;
+mod error;
 mod ffmpeg;
 mod options;
+mod quota;
+mod schedule;
+mod sections;
+mod template;
 mod thumbnail;
 mod youtube;
 
-use options::{Command, Options};
+use error::Error;
+use options::{
+    AuthCommand, BrandingCommand, ChangeMode, Command, Options, PublishDate, ScheduleCommand,
+    WatermarkCommand,
+};
+use quota::Operation;
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() {
     env_logger::init();
+    let result = run().await;
+    quota::save();
+    if let Err(err) = result {
+        eprintln!("Error: {:?}", err);
+        std::process::exit(error::exit_code(&err));
+    }
+}
+
+/// runs the command, main reports its error and exits with the code of the error kind
+async fn run() -> anyhow::Result<()> {
     let options = Options::parse();
+    let budget = options.quota_budget;
+    let login = options.login.clone();
+    let scopes = options.cmd.scopes();
+    quota::set_state_file(quota::STATE_FILE);
+    youtube::retry::set_policy(youtube::retry::RetryPolicy {
+        max_attempts: options.attempts.max(1),
+        ..Default::default()
+    });
     match options.cmd {
         Command::Upload(mut options) => {
+            // the client is only built when the API is needed, --pretend works offline
+            // unless the free slots have to be looked up
+            let mut client = None;
+            if let PublishDate::NextFreeSlot(_) = options.publish_at {
+                let cl = youtube::YoutubeClient::new(&login, &scopes).await?;
+                options.scheduled = scheduled_dates(&cl, &options.timezone).await?;
+                client = Some(cl);
+            }
             if options.pretend {
                 println!("publish-at: {:?}", options.publish_at);
-                println!("publish-datetime: {}", options.publish_datetime()?);
-                if let Ok(episode_nr) = options.episode_nr() {
-                    println!("episode_nr: {} (0x{:X})", episode_nr, episode_nr);
-                    println!(r#"youtube-title: "{:X}. {}""#, episode_nr, options.title());
+                println!(
+                    "publish-datetime: {}",
+                    options
+                        .publish_datetime()?
+                        .unwrap_or_else(|| "immediately".to_string())
+                );
+                println!("privacy-status: {}", options.upload_privacy());
+                if let Ok(episode) = options.episode() {
+                    println!("episode_nr: {} (0x{:X})", episode.number, episode.number);
+                    if let Some(season) = episode.season {
+                        println!("season: {}", season);
+                    }
                 } else {
                     println!("episode_nr: n.a.");
-                    println!(r#"youtube-title: "{}""#, options.title());
                 }
-                println!("catgegory: {:?}", options.category);
-                println!("thumb-title: {:?}", options.title());
+                println!(r#"youtube-title: "{}""#, options.youtube_title()?);
+                match categories::load_cached(&options.region) {
+                    Some(cats) => {
+                        let category = categories::resolve(&cats, &options.category)?;
+                        println!("category: {} ({})", category.title, category.id);
+                    }
+                    None => println!("category: {:?} (not validated)", options.category),
+                }
+                println!("thumb-title: {:?}", options.thumbnail_text()?);
                 println!("youtube-description: {}", &options.description);
                 println!("youtube-tags: {:?}", &options.tags());
-                std::process::exit(0);
+                return Ok(());
+            }
+            let mut estimate = Operation::VideoInsert.cost() + Operation::ThumbnailSet.cost();
+            if options.playlist_id.is_some() {
+                estimate += Operation::PlaylistInsert.cost();
+            }
+            quota::check_budget(estimate, budget)?;
+            let client = match client {
+                Some(client) => client,
+                None => youtube::YoutubeClient::new(&login, &scopes).await?,
+            };
+            let cats = video_categories(&client, &options.region, false).await?;
+            options.category = categories::resolve(&cats, &options.category)?.id.clone();
+            let mut upload_fn = options.file.clone();
+            let mut thumb_second = options.thumb_second;
+            if options.intro.is_some() || options.outro.is_some() {
+                let intro_len = match &options.intro {
+                    Some(intro) => ffmpeg::duration(&options.ffprobe_bin, intro)?.round() as u64,
+                    None => 0,
+                };
+                upload_fn = ffmpeg::concat(
+                    &options.ffmpeg_bin,
+                    &options.ffprobe_bin,
+                    &options.file,
+                    options.intro.as_deref(),
+                    options.outro.as_deref(),
+                )?;
+                println!("concatenated: {:?}", &upload_fn);
+                options.description = chapters::shift(&options.description, intro_len, "Intro");
+                thumb_second += intro_len as usize;
             }
             // if no thumbnail given, check if video-filename with .jpg extension exists (=default
             // thumbnail), if not make one with that filename
@@ -41,118 +124,443 @@ async fn main() -> anyhow::Result<()> {
                 let mut thumb_path = PathBuf::from(&options.file);
                 thumb_path.set_extension("jpg");
                 if !thumb_path.exists() {
-                    let screenshot_fn = ffmpeg::bg_from_video(
-                        &options.ffmpeg_bin,
-                        &options.file,
-                        options.thumb_second,
-                    );
+                    let screenshot_fn =
+                        ffmpeg::bg_from_video(&options.ffmpeg_bin, &upload_fn, thumb_second)?;
                     thumbnail::make_thumbnail(
                         &thumb_path,
                         &screenshot_fn,
                         &options.thumbnail_watermark,
-                        &options.title(),
-                    );
+                        &options.thumbnail_text()?,
+                    )?;
                 }
                 options.thumbnail = Some(thumb_path);
             }
             println!("thumbnail-path: {:?}", &options.thumbnail);
-            let mut cl = youtube::video_service().await;
-            let video_id = youtube::upload_file(&mut cl, &options).await?;
+            if let Some(target) = options.loudnorm_target() {
+                let stats = ffmpeg::measure_loudness(&options.ffmpeg_bin, &upload_fn, &target)?;
+                println!("loudness: {}", stats);
+                if options.loudnorm.is_some() {
+                    upload_fn = ffmpeg::normalize_loudness(
+                        &options.ffmpeg_bin,
+                        &upload_fn,
+                        &target,
+                        &stats,
+                    )?;
+                    println!("normalized-audio: {:?}", &upload_fn);
+                }
+            }
+            let video_id = youtube::upload_file(&client, &options, &upload_fn).await?;
             println!("upload video_id {:?}", &video_id);
 
-            if options.thumbnail.is_some() {
-                let mut cl = youtube::thumbnail_service().await;
-                let _ = youtube::upload_thumbnail(
-                    &mut cl,
-                    &video_id,
-                    options.thumbnail.as_ref().unwrap(),
-                )
-                .await;
+            if let Some(thumbnail) = &options.thumbnail {
+                let _ = youtube::upload_thumbnail(&client, &video_id, thumbnail).await;
             }
             if options.playlist_id.is_some() {
-                let mut cl = youtube::playlist_service().await;
-                let _ = youtube::add_to_playlist(&mut cl, &options, &video_id).await;
+                let _ = youtube::add_to_playlist(&client, &options, &video_id).await;
+            }
+            if options.wait || options.after_processing {
+                youtube::wait_for_processing(&client, &video_id).await?;
+                if options.after_processing {
+                    youtube::set_privacy(&client, &video_id, options.published_privacy()).await?;
+                }
             }
         }
         Command::List(options) => {
             if options.publish_methods {
                 crate::options::print_publish_date_enum();
-                std::process::exit(0);
+                return Ok(());
+            }
+            if options.categories {
+                let client = youtube::YoutubeClient::new(&login, &scopes).await?;
+                for c in video_categories(&client, &options.region, options.refresh).await? {
+                    let note = if c.assignable {
+                        ""
+                    } else {
+                        " (not assignable)"
+                    };
+                    println!("{:>3} {}{}", c.id, c.title, note);
+                }
+                return Ok(());
             }
             if options.yt_top5 {
-                let mut cl = youtube::video_service().await;
-                youtube::video_list(&mut cl).await;
-                std::process::exit(1);
+                let client = youtube::YoutubeClient::new(&login, &scopes).await?;
+                youtube::video_list(&client).await?;
+                return Ok(());
             }
             if options.uploaded {
-                eprintln!("Not yet implemented");
-                std::process::exit(1);
+                anyhow::bail!("list --uploaded is not yet implemented");
             }
         }
         Command::Update(options) => {
-            let mut cl = youtube::video_service().await;
-            let mut chsrv = youtube::channels_service().await;
+            let client = youtube::YoutubeClient::new(&login, &scopes).await?;
             let vids = if options.video_id == "uploaded" {
-                youtube::uploaded_video_list(&mut chsrv).await?
+                youtube::uploaded_video_list(&client).await?
+            } else {
+                vec![youtube::YtVid::from_id(&client, &options.video_id).await?]
+            };
+            let command = batch_command();
+            let vids = if options.resume {
+                let pending = quota::pending(&command)?;
+                vids.into_iter()
+                    .filter(|v| pending.contains(&v.id))
+                    .collect()
             } else {
-                vec![youtube::YtVid::from_id(&mut cl, &options.video_id).await?]
+                vids
             };
+            let ids: Vec<&str> = vids.iter().map(|v| v.id.as_str()).collect();
             if let Some(new_thumb) = options.generate_thumbnail {
+                quota::begin(&command, &ids, Operation::ThumbnailSet.cost(), budget)?;
                 let entries = std::fs::read_dir(&new_thumb)?
                     .map(|res| res.map(|e| e.path()))
                     .collect::<Result<Vec<_>, std::io::Error>>()?;
                 println!("{:#?}", entries);
                 let mov_ext = Some(std::ffi::OsStr::new("mov"));
-                let mut tsrv = youtube::thumbnail_service().await;
                 for v in vids {
-                    if let Some((episode_nr, ep_title)) = &v.title.split_once('.') {
-                        // text on thumbnail is without episode nr and series info
-                        let ep_title = match ep_title.split_once('-') {
-                            Some((t, _)) => t.trim(),
-                            None => ep_title,
-                        };
-                        let video_fn: PathBuf = entries
-                            .iter()
-                            .filter(|vfn| {
-                                vfn.file_name()
-                                    .map(|x| x.to_str().unwrap().starts_with(episode_nr))
-                                    .unwrap()
-                                    && vfn.extension() == mov_ext
-                            })
-                            .take(1)
-                            .collect();
-                        println!("Video {} {:?}", &episode_nr, &video_fn);
-                        let mut thumb_path = PathBuf::from(&video_fn);
-                        thumb_path.set_extension("jpg");
-                        let screenshot_fn = ffmpeg::bg_from_video(
-                            &options.ffmpeg_bin,
-                            &video_fn,
-                            options.thumb_second,
-                        );
-                        thumbnail::make_thumbnail(
-                            &thumb_path,
-                            &screenshot_fn,
-                            &options.thumbnail_watermark,
-                            ep_title,
-                        );
-                        let _ = youtube::upload_thumbnail(&mut tsrv, &v.id, thumb_path).await;
+                    // the youtube title holds the episode nr, text on thumbnail comes from template
+                    let values = match template::captures(&options.title_pattern, &v.title) {
+                        Ok(values) => values,
+                        Err(e) => {
+                            println!("skipping {}: {}", &v.id, e);
+                            // skipped videos aren't retried by --resume
+                            quota::done(&command, &v.id);
+                            continue;
+                        }
+                    };
+                    let episode_nr = match values.get("ep") {
+                        Some(template::Value::Text(ep)) => ep.clone(),
+                        _ => {
+                            return Err(Error::Config(
+                                "title-pattern needs a group named ep".to_string(),
+                            )
+                            .into())
+                        }
+                    };
+                    let thumb_text = template::render(&options.thumb_template, &values)?;
+                    let video_fn: PathBuf = entries
+                        .iter()
+                        .filter(|vfn| {
+                            vfn.file_name()
+                                .map_or(false, |x| x.to_string_lossy().starts_with(&episode_nr))
+                                && vfn.extension() == mov_ext
+                        })
+                        .take(1)
+                        .collect();
+                    println!("Video {} {:?}", &episode_nr, &video_fn);
+                    let mut thumb_path = PathBuf::from(&video_fn);
+                    thumb_path.set_extension("jpg");
+                    let screenshot_fn = ffmpeg::bg_from_video(
+                        &options.ffmpeg_bin,
+                        &video_fn,
+                        options.thumb_second,
+                    )?;
+                    thumbnail::make_thumbnail(
+                        &thumb_path,
+                        &screenshot_fn,
+                        &options.thumbnail_watermark,
+                        &thumb_text,
+                    )?;
+                    let uploaded = youtube::upload_thumbnail(&client, &v.id, thumb_path).await;
+                    if let Err(e) = quota::track(&command, &v.id, uploaded) {
+                        if quota::is_exceeded(&e) {
+                            return Err(e);
+                        }
+                        // the other videos are still updated, failed ones aren't retried
+                        // by --resume
+                        eprintln!("warning: thumbnail of {} not set: {:#}", &v.id, e);
+                        quota::done(&command, &v.id);
                     }
                 }
             } else if let Some(desc) = options.description {
                 let new_desc = std::fs::read_to_string(&desc)?;
-                for v in vids {
-                    youtube::change_description(&mut cl, &v.id, &new_desc, options.change_desc)
-                        .await?;
+                quota::begin(&command, &ids, UPDATE_COST, budget)?;
+                for v in &vids {
+                    let changed =
+                        youtube::change_description(&client, &v.id, &new_desc, options.change_desc)
+                            .await;
+                    quota::track(&command, &v.id, changed)?;
                 }
+            } else if let Some(desc_replace) = options.desc_replace {
+                let re = regex::Regex::new(&desc_replace[0])?;
+                let changed = vids
+                    .iter()
+                    .filter_map(|v| {
+                        description::replace(&v.description, &re, &desc_replace[1])
+                            .map(|new_desc| (v, new_desc))
+                    })
+                    .collect();
+                review_descriptions(
+                    &client,
+                    &vids,
+                    changed,
+                    options.dry_run,
+                    options.yes,
+                    &command,
+                    budget,
+                )
+                .await?;
+            } else if options.section.is_some() || options.remove_section.is_some() {
+                let changed = match (&options.section, &options.from, &options.remove_section) {
+                    (Some(name), Some(from), _) => {
+                        let content = std::fs::read_to_string(from)?;
+                        vids.iter()
+                            .map(|v| (v, sections::upsert(&v.description, name, &content)))
+                            .collect::<Vec<_>>()
+                    }
+                    (_, _, Some(name)) => vids
+                        .iter()
+                        .map(|v| (v, sections::remove(&v.description, name)))
+                        .collect(),
+                    _ => return Err(Error::Config("--section needs --from".to_string()).into()),
+                };
+                let changed = changed
+                    .into_iter()
+                    .filter(|(v, new_desc)| &v.description != new_desc)
+                    .collect();
+                review_descriptions(
+                    &client,
+                    &vids,
+                    changed,
+                    options.dry_run,
+                    options.yes,
+                    &command,
+                    budget,
+                )
+                .await?;
             } else if let Some(playlist_id) = options.add_to_playlist {
                 eprintln!(
                     "at some point in time this will add a video_id to a playlist: {}",
                     &playlist_id
                 );
+            } else if options.metadata.is_set() || options.status.is_set() {
+                let category_id = match &options.metadata.category {
+                    Some(category) => {
+                        let cats =
+                            video_categories(&client, &options.metadata.region, false).await?;
+                        Some(categories::resolve(&cats, category)?.id.clone())
+                    }
+                    None => None,
+                };
+                if !options.dry_run {
+                    quota::begin(&command, &ids, UPDATE_COST, budget)?;
+                }
+                for v in &vids {
+                    let updated = youtube::update_metadata(
+                        &client,
+                        &v.id,
+                        &options.metadata,
+                        &options.status,
+                        category_id.as_deref(),
+                        options.dry_run,
+                    )
+                    .await;
+                    if options.dry_run {
+                        updated?;
+                    } else {
+                        quota::track(&command, &v.id, updated)?;
+                    }
+                }
             } else {
                 eprintln!("not implemented");
             }
         }
+        Command::Schedule(options) => match options.cmd {
+            ScheduleCommand::Show(options) => {
+                let client = youtube::YoutubeClient::new(&login, &scopes).await?;
+                let calendar =
+                    options.calendar(scheduled_dates(&client, &options.timezone).await?)?;
+                let today = date_compute::today(&options.timezone);
+                let next_free = calendar.next_free(today).ok();
+                for (date, slot) in calendar.slots(today).take(options.count) {
+                    let state = match slot {
+                        schedule::Slot::Free if Some(date) == next_free => {
+                            "free <- next free slot".to_string()
+                        }
+                        schedule::Slot::Free => "free".to_string(),
+                        schedule::Slot::Blackout => "blackout".to_string(),
+                        schedule::Slot::Taken(title) => format!("taken: {}", title),
+                    };
+                    println!("{} {} {}", date, date.format("%a"), state);
+                }
+            }
+        },
+        Command::Backup(options) => {
+            let client = youtube::YoutubeClient::new(&login, &scopes).await?;
+            let channel_id = youtube::my_channel_id(&client).await?;
+            let vids = youtube::uploaded_video_list(&client).await?;
+            let now = chrono::Utc::now();
+            let archive = youtube::backup::Archive::new(
+                date_compute::format_rfc3339(now),
+                channel_id,
+                youtube::backup_videos(&client, &vids).await?,
+            );
+            let output = options.output.unwrap_or_else(|| {
+                PathBuf::from(format!("backup-{}.json", now.format("%Y-%m-%d")))
+            });
+            archive.save(&output)?;
+            println!("{} videos saved to {:?}", archive.videos.len(), output);
+        }
+        Command::Restore(options) => {
+            let archive = youtube::backup::Archive::load(&options.from)?;
+            println!(
+                "backup of channel {} from {}",
+                archive.channel_id, archive.created_at
+            );
+            let client = youtube::YoutubeClient::new(&login, &scopes).await?;
+            youtube::restore_videos(
+                &client,
+                &archive,
+                options.video_id.as_deref(),
+                &options.fields(),
+                options.dry_run,
+                |count| Ok(options.yes || confirm(&format!("restore {} videos?", count))?),
+            )
+            .await?;
+        }
+        Command::Quota => {
+            let usage = quota::load();
+            println!(
+                "{} of {} quota units used on {} (Pacific Time), {} left",
+                usage.used,
+                budget,
+                usage.day,
+                quota::remaining(&usage, budget)
+            );
+            for (op, units) in &usage.operations {
+                println!("{:>6} {}", units, op);
+            }
+            for batch in usage.batches {
+                println!(
+                    "interrupted batch with {} videos left, resume with: rsytup {} --resume",
+                    batch.pending.len(),
+                    batch.command
+                );
+            }
+        }
+        Command::Auth(options) => match options.cmd {
+            AuthCommand::Login(_) => {
+                youtube::YoutubeClient::new(&login, &scopes)
+                    .await?
+                    .login()
+                    .await?;
+                println!("authorized, tokens are cached");
+            }
+            AuthCommand::Status => {
+                youtube::YoutubeClient::new(&login, &scopes)
+                    .await?
+                    .auth_status()
+                    .await?
+            }
+            AuthCommand::Logout => {
+                youtube::YoutubeClient::new(&login, &scopes)
+                    .await?
+                    .logout()
+                    .await?
+            }
+        },
+        #[cfg(feature = "fake-api")]
+        Command::FakeApi(options) => youtube::serve_fake_api(&options).await?,
+        Command::Branding(options) => match options.cmd {
+            BrandingCommand::Watermark(cmd) => {
+                let client = youtube::YoutubeClient::new(&login, &scopes).await?;
+                let channel_id = youtube::my_channel_id(&client).await?;
+                let mut cl = client.watermarks()?;
+                match cmd {
+                    WatermarkCommand::Set(options) => {
+                        youtube::set_watermark(&mut cl, &channel_id, &options).await?
+                    }
+                    WatermarkCommand::Unset => {
+                        youtube::unset_watermark(&mut cl, &channel_id).await?
+                    }
+                }
+            }
+        },
+    }
+    Ok(())
+}
+
+/// publish dates of all videos scheduled on your channel, in local time of `tz`
+async fn scheduled_dates(
+    client: &youtube::YoutubeClient,
+    tz: &chrono_tz::Tz,
+) -> anyhow::Result<Vec<(chrono::NaiveDate, String)>> {
+    let vids = youtube::uploaded_video_list(client).await?;
+    let mut dates = vec![];
+    for (publish_at, title) in youtube::scheduled_videos(client, &vids).await? {
+        dates.push((date_compute::parse_rfc3339_date(&publish_at, tz)?, title));
+    }
+    Ok(dates)
+}
+
+/// quota of a fetch-modify-write update of one video
+const UPDATE_COST: u32 = Operation::List.cost() + Operation::VideoUpdate.cost();
+
+/// command line identifying a batch, without --resume
+fn batch_command() -> String {
+    std::env::args()
+        .skip(1)
+        .filter(|arg| arg != "--resume")
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// shows which videos and description lines change, after confirmation only the changed
+/// descriptions are written
+async fn review_descriptions(
+    api: &impl youtube::YoutubeApi,
+    vids: &[youtube::YtVid],
+    changed: Vec<(&youtube::YtVid, String)>,
+    dry_run: bool,
+    yes: bool,
+    command: &str,
+    budget: u32,
+) -> anyhow::Result<()> {
+    println!("{} of {} videos change:", changed.len(), vids.len());
+    for (v, _) in &changed {
+        println!("  {} {}", v.id, v.title);
+    }
+    for (v, new_desc) in &changed {
+        println!("\n{} {}", v.id, v.title);
+        for line in description::diff_lines(&v.description, new_desc) {
+            println!("  {}", line);
+        }
+    }
+    if changed.is_empty() || dry_run {
+        return Ok(());
+    }
+    if !yes && !confirm(&format!("update {} videos?", changed.len()))? {
+        anyhow::bail!("aborted, nothing was updated");
+    }
+    let ids: Vec<&str> = changed.iter().map(|(v, _)| v.id.as_str()).collect();
+    quota::begin(command, &ids, UPDATE_COST, budget)?;
+    for (v, new_desc) in &changed {
+        let replaced = youtube::change_description(api, &v.id, new_desc, ChangeMode::Replace).await;
+        quota::track(command, &v.id, replaced)?;
     }
     Ok(())
 }
+
+/// asks a yes/no question on the terminal, anything but y or yes is a no
+fn confirm(question: &str) -> anyhow::Result<bool> {
+    use std::io::Write;
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// video categories of a region, fetched only if not cached yet or `refresh` is requested
+async fn video_categories(
+    client: &youtube::YoutubeClient,
+    region: &str,
+    refresh: bool,
+) -> anyhow::Result<Vec<categories::VideoCategory>> {
+    if !refresh {
+        if let Some(cats) = categories::load_cached(region) {
+            return Ok(cats);
+        }
+    }
+    let cats = youtube::video_categories(client, region).await?;
+    categories::store(region, &cats)?;
+    Ok(cats)
+}
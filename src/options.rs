@@ -4,13 +4,21 @@ use strum::{EnumMessage, IntoEnumIterator};
 // SPDX-License-Identifier: GPL-3.0-or-later
 // Copyright © 2021 Michael Kefeder
 use crate::date_compute;
+use crate::episode::{Episode, EpisodeScheme};
+use crate::ffmpeg::LoudnormTarget;
+use crate::schedule::{self, Calendar, Rule};
+use crate::template::{self, Value};
+use chrono::NaiveDate;
+use chrono_tz::Tz;
+use regex::Regex;
+use std::collections::HashMap;
 use std::error::Error;
 use std::path::PathBuf;
 
 #[derive(Debug, Clone, strum::EnumIter, strum::EnumMessage)]
 #[strum(serialize_all = "kebab_case")]
 pub enum PublishDate {
-    /// current date at 0 o'clock, publishes therefore as soon as possible
+    /// publishes right after upload as public (or unlisted), no publish date is set
     Asap,
     /// compute date of coming weekday, e.g. friday computes the date of next friday
     Coming(String),
@@ -20,6 +28,10 @@ pub enum PublishDate {
     IsoDate(String),
     /// uses given ISO formatted date and time
     IsoDateTime(String),
+    /// next date matching a cadence rule, e.g. rule=weekly:tue,fri, rule=biweekly:mon or rule=monthly:first-wed
+    Rule(String),
+    /// like rule, but skips dates where a video is already scheduled on your channel
+    NextFreeSlot(String),
 }
 
 impl std::str::FromStr for PublishDate {
@@ -33,6 +45,8 @@ impl std::str::FromStr for PublishDate {
             "weeks-from-episode" => Ok(PublishDate::WeeksFromEpisode),
             "iso-date" => Ok(PublishDate::IsoDate(v)),
             "iso-date-time" => Ok(PublishDate::IsoDateTime(v)),
+            "rule" => Ok(PublishDate::Rule(v)),
+            "next-free-slot" => Ok(PublishDate::NextFreeSlot(v)),
             _ => anyhow::bail!("variant not found"),
         }
     }
@@ -46,7 +60,7 @@ pub enum ChangeMode {
     Prepend,
 }
 
-#[derive(Debug, clap::ValueEnum, Clone, Copy, strum::Display)]
+#[derive(Debug, clap::ValueEnum, Clone, Copy, PartialEq, Eq, strum::Display)]
 #[clap(rename_all = "kebab_case")]
 #[strum(serialize_all = "kebab_case")]
 pub enum PrivacyStates {
@@ -55,22 +69,135 @@ pub enum PrivacyStates {
     Unlisted,
 }
 
+#[derive(Debug, clap::ValueEnum, Clone, Copy, strum::Display)]
+#[clap(rename_all = "kebab_case")]
+#[strum(serialize_all = "camelCase")]
+pub enum License {
+    Youtube,
+    CreativeCommon,
+}
+
+/// how the OAuth2 authorization is obtained
+#[derive(Debug, clap::ValueEnum, Clone, Copy, PartialEq, Eq, strum::Display)]
+#[clap(rename_all = "kebab_case")]
+#[strum(serialize_all = "kebab_case")]
+pub enum AuthFlow {
+    /// opens the browser, the code is received by a local webserver
+    Browser,
+    /// shows a code to enter at google.com/device, for servers without browser
+    Device,
+    /// shows the URL to open anywhere, the code has to be pasted
+    Manual,
+    /// token cache content from the environment variable RSYTUP_TOKEN_CACHE, e.g. for CI
+    Env,
+    /// access token from RSYTUP_ACCESS_TOKEN as is, it is never refreshed. For `fake-api`
+    Token,
+}
+
+/// storage of the cached OAuth2 tokens
+#[derive(Debug, clap::ValueEnum, Clone, Copy, PartialEq, Eq, strum::Display)]
+#[clap(rename_all = "kebab_case")]
+#[strum(serialize_all = "kebab_case")]
+pub enum TokenBackend {
+    /// plain JSON file
+    File,
+    /// file encrypted with the passphrase in RSYTUP_TOKEN_PASSPHRASE, raw keys aren't supported
+    Encrypted,
+    /// keyring of the operating system, needs the keyring feature
+    Keyring,
+}
+
+/// OAuth2 scopes of the YouTube API
+#[derive(Debug, clap::ValueEnum, Clone, Copy, PartialEq, Eq, strum::Display)]
+#[clap(rename_all = "kebab_case")]
+#[strum(serialize_all = "kebab_case")]
+pub enum Scope {
+    /// view your YouTube account
+    Readonly,
+    /// manage your YouTube videos
+    Upload,
+    /// see, edit, and permanently delete your YouTube videos, ratings, comments and captions
+    ForceSsl,
+    /// manage your YouTube account
+    Manage,
+}
+
+/// metadata which can be restored from a backup
+#[derive(Debug, clap::ValueEnum, Clone, Copy, PartialEq, Eq)]
+#[clap(rename_all = "kebab_case")]
+pub enum RestoreField {
+    Title,
+    Description,
+    Tags,
+    Category,
+    /// default language and audio language
+    Language,
+    /// privacy, publish date, license and the other status settings
+    Status,
+    Localizations,
+}
+
 pub fn print_publish_date_enum() {
     for m in PublishDate::iter() {
         println!(
             "{:?} {}",
             m.get_serializations(),
-            m.get_documentation().unwrap()
+            m.get_documentation().unwrap_or_default()
         );
     }
 }
 
-#[derive(Debug, clap::ValueEnum, Clone, Copy)]
+/// corner of the video the watermark is shown in
+#[derive(Debug, clap::ValueEnum, Clone, Copy, strum::Display)]
 #[clap(rename_all = "kebab_case")]
-pub enum Categories {
-    Science = 28,
-    People = 22,
-    Comedy = 23,
+#[strum(serialize_all = "camelCase")]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+/// when the watermark appears, format <method>=<duration> e.g. offset-from-end=30s
+#[derive(Debug, Clone, Copy)]
+pub enum WatermarkTiming {
+    /// milliseconds after start of the video
+    OffsetFromStart(u64),
+    /// milliseconds before end of the video
+    OffsetFromEnd(u64),
+}
+
+impl std::str::FromStr for WatermarkTiming {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (k, v) = parse_key_val::<String, String>(s)?;
+        match k.as_str() {
+            "offset-from-start" => Ok(WatermarkTiming::OffsetFromStart(parse_duration_ms(&v)?)),
+            "offset-from-end" => Ok(WatermarkTiming::OffsetFromEnd(parse_duration_ms(&v)?)),
+            _ => anyhow::bail!(
+                "timing must be offset-from-start=<duration> or offset-from-end=<duration>"
+            ),
+        }
+    }
+}
+
+/// Parse a duration like `1500ms`, `30s` or `2m` into milliseconds, no unit means seconds
+pub fn parse_duration_ms(s: &str) -> anyhow::Result<u64> {
+    let s = s.trim();
+    let (number, factor) = if let Some(n) = s.strip_suffix("ms") {
+        (n, 1)
+    } else if let Some(n) = s.strip_suffix('s') {
+        (n, 1000)
+    } else if let Some(n) = s.strip_suffix('m') {
+        (n, 60_000)
+    } else {
+        (s, 1000)
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| anyhow::anyhow!("invalid duration {:?}, use e.g. 1500ms, 30s or 2m", s))?;
+    Ok(number * factor)
 }
 
 /// Parse a single key-value pair from `KEY=VALUE` format
@@ -88,6 +215,16 @@ where
     }
 }
 
+/// Parse a localization in `LANGUAGE=VALUE` format, unlike `parse_key_val` the `=` is required
+fn parse_localized(s: &str) -> anyhow::Result<(String, String)> {
+    match s.split_once('=') {
+        Some((language, value)) if !language.is_empty() && !value.is_empty() => {
+            Ok((language.to_string(), value.to_string()))
+        }
+        _ => anyhow::bail!("expected <language>=<value>, got {:?}", s),
+    }
+}
+
 #[derive(Debug, clap::Parser)]
 #[clap(
     name = "Rust Youtube uploader",
@@ -96,6 +233,60 @@ where
 pub(crate) struct Options {
     #[clap(subcommand)]
     pub cmd: Command,
+    /// daily quota units available to rsytup, batches which would exceed it are refused
+    #[clap(
+        long,
+        global = true,
+        env = "RSYTUP_QUOTA_BUDGET",
+        default_value = "10000"
+    )]
+    pub quota_budget: u32,
+    /// attempts of API calls failing for transient reasons like server errors or rate limits
+    #[clap(long, global = true, env = "RSYTUP_ATTEMPTS", default_value = "5")]
+    pub attempts: u32,
+    #[clap(flatten)]
+    pub login: LoginOptions,
+}
+
+/// how rsytup is authorized, where the tokens are kept and which API is used
+#[derive(Debug, Clone, clap::Args)]
+pub(crate) struct LoginOptions {
+    /// how to authorize rsytup to access your channel
+    #[clap(
+        long,
+        global = true,
+        env = "RSYTUP_AUTH_FLOW",
+        default_value = "browser"
+    )]
+    pub auth_flow: AuthFlow,
+    /// where the OAuth2 tokens are cached, not used by the env auth flow
+    #[clap(
+        long,
+        global = true,
+        env = "RSYTUP_TOKEN_STORAGE",
+        default_value = "file"
+    )]
+    pub token_storage: TokenBackend,
+    /// token cache file of the file and encrypted storage, created readable by the owner only
+    #[clap(
+        long,
+        global = true,
+        env = "RSYTUP_TOKEN_FILE",
+        default_value = "tokencache.json"
+    )]
+    pub token_file: PathBuf,
+    /// access token of the token auth flow
+    #[clap(
+        long,
+        global = true,
+        env = "RSYTUP_ACCESS_TOKEN",
+        hide_env_values = true
+    )]
+    pub access_token: Option<String>,
+    /// API server instead of https://youtube.googleapis.com/, e.g. the one of `rsytup fake-api`.
+    /// only accepted on localhost or with `--auth-flow token`
+    #[clap(long, global = true, env = "RSYTUP_API_BASE_URL")]
+    pub api_base_url: Option<String>,
 }
 
 #[derive(Debug, clap::Parser)]
@@ -106,6 +297,55 @@ pub(crate) enum Command {
     List(ListOptions),
     /// Update existing Content
     Update(UpdateOptions),
+    /// Manage the channel branding
+    Branding(BrandingOptions),
+    /// Release calendar computed from cadence rules
+    Schedule(ScheduleOptions),
+    /// Export snippet, status, localizations and thumbnail URLs of all uploaded videos
+    Backup(BackupOptions),
+    /// Put back metadata from a backup
+    Restore(RestoreOptions),
+    /// Show the estimated API quota used today
+    Quota,
+    /// Manage the authorization of rsytup
+    Auth(AuthOptions),
+    /// Serve an in-memory YouTube API on localhost for testing
+    #[cfg(feature = "fake-api")]
+    #[clap(hide = true)]
+    FakeApi(FakeApiOptions),
+}
+
+impl Command {
+    /// least privileged scopes the command needs, listing the channel and video categories
+    /// requests a read-only token on demand if none of these allow reading
+    pub fn scopes(&self) -> Vec<Scope> {
+        match self {
+            Command::Upload(options) => {
+                let mut scopes = vec![Scope::Upload];
+                if let PublishDate::NextFreeSlot(_) = options.publish_at {
+                    scopes.push(Scope::Readonly);
+                }
+                // waiting lists the video, making it public afterwards updates it
+                if options.wait {
+                    scopes.push(Scope::Readonly);
+                }
+                if options.playlist_id.is_some() || options.after_processing {
+                    scopes.push(Scope::ForceSsl);
+                }
+                scopes.dedup();
+                scopes
+            }
+            Command::List(_) | Command::Schedule(_) | Command::Backup(_) => vec![Scope::Readonly],
+            Command::Update(_) | Command::Restore(_) => vec![Scope::ForceSsl],
+            Command::Branding(_) => vec![Scope::Upload],
+            Command::Auth(AuthOptions {
+                cmd: AuthCommand::Login(options),
+            }) => options.scopes.clone(),
+            Command::Quota | Command::Auth(_) => vec![],
+            #[cfg(feature = "fake-api")]
+            Command::FakeApi(_) => vec![],
+        }
+    }
 }
 
 #[derive(Debug, clap::Parser)]
@@ -135,21 +375,59 @@ pub(crate) struct UploadOptions {
     /// publishing day-time
     #[clap(short = 'T', long, default_value = "08:00:00")]
     pub publish_time: String,
+    /// time zone of publish dates and times, e.g. Europe/Vienna
+    #[clap(long, env = "RSYTUP_TIMEZONE", default_value = "UTC")]
+    pub timezone: Tz,
     /// Number of episode (if not in title)
     #[clap(short, long)]
-    pub episode_nr: Option<u8>,
+    pub episode_nr: Option<u32>,
+    /// Season of episode (if not in title)
+    #[clap(long)]
+    pub season: Option<u32>,
+    /// how to find the episode number in the title: hex-prefix, decimal-prefix, season-episode
+    /// (S02E13), regex=<regex> or regex-hex=<regex> using the group named ep or the first group
+    #[clap(long, default_value = "hex-prefix")]
+    pub episode_scheme: EpisodeScheme,
+    /// regex matched against the filename (without extension), its named groups can be used
+    /// as placeholders in title and thumbnail templates, e.g. "^(?P<ep>\d+)\. (?P<name>.*)$"
+    #[clap(long)]
+    pub filename_pattern: Option<Regex>,
+    /// name of the series, placeholder {series}
+    #[clap(long)]
+    pub series: Option<String>,
+    /// youtube title, placeholders {title}, {stem}, {ep}, {season}, {series} and the named
+    /// groups of filename-pattern, numbers can be formatted e.g. "{ep:X}. {name} - {series}"
+    /// (hex) or "S{season:02}E{ep:02} {title}", default is the plain title
+    #[clap(long)]
+    pub title_template: Option<String>,
+    /// thumbnail text, same placeholders as title-template e.g. "{name}", default is the plain
+    /// title
+    #[clap(long)]
+    pub thumb_template: Option<String>,
     /// add video to Playlist (if given)
     #[clap(long)]
     pub playlist_id: Option<String>,
     /// comma separated keywords list
     #[clap(long, default_value = "rust,tutorial,youtube,upload,rsytup")]
     pub keywords: String,
-    /// privacy status
+    /// privacy status, scheduled videos have to be private until published
     #[clap(long, default_value = "private")]
     pub privacy_status: PrivacyStates,
-    /// Category
+    /// upload as private, wait until YouTube processed the video, then make it public (or
+    /// unlisted if that privacy status was given)
+    #[clap(long, conflicts_with = "publish_at")]
+    pub after_processing: bool,
+    /// wait until YouTube processed the video, fails if the video was rejected
+    #[clap(long)]
+    pub wait: bool,
+    #[clap(flatten)]
+    pub status: StatusOptions,
+    /// Category name or ID, e.g. science or 28, see `list --categories`
     #[clap(long, default_value = "science")]
-    pub category: Categories,
+    pub category: String,
+    /// region the category is looked up in
+    #[clap(long, default_value = "US")]
+    pub region: String,
     /// Date of First episode
     #[clap(long, default_value = "2020-09-01")]
     pub first_episode_date: String,
@@ -159,6 +437,64 @@ pub(crate) struct UploadOptions {
     /// path to ffmpeg binary
     #[clap(long, default_value = "ffmpeg")]
     pub ffmpeg_bin: PathBuf,
+    /// measure and report integrated loudness, true peak and loudness range of the audio
+    #[clap(long)]
+    pub measure_loudness: bool,
+    /// normalize audio to this integrated loudness in LUFS before upload, e.g. -14
+    #[clap(long, allow_hyphen_values = true)]
+    pub loudnorm: Option<f32>,
+    /// maximum true peak in dBTP used for loudness normalization
+    #[clap(long, default_value = "-1.5", allow_hyphen_values = true)]
+    pub loudnorm_true_peak: f32,
+    /// loudness range in LU used for loudness normalization
+    #[clap(long, default_value = "11")]
+    pub loudnorm_lra: f32,
+    /// clip to put in front of the video, chapters and thumbnail second are shifted by its length
+    #[clap(long)]
+    pub intro: Option<PathBuf>,
+    /// clip to append to the video, e.g. an end-card
+    #[clap(long)]
+    pub outro: Option<PathBuf>,
+    /// path to ffprobe binary
+    #[clap(long, default_value = "ffprobe")]
+    pub ffprobe_bin: PathBuf,
+    /// dates never to publish at, comma separated ISO dates or ranges <from>..<to>
+    #[clap(long, value_delimiter = ',')]
+    pub blackout: Vec<String>,
+    /// publish dates of videos already scheduled on the channel, used by next-free-slot
+    #[clap(skip)]
+    pub scheduled: Vec<(NaiveDate, String)>,
+}
+
+/// video status settings, unset ones are left as they are when updating
+#[derive(Debug, clap::Args)]
+pub(crate) struct StatusOptions {
+    /// license of the video
+    #[clap(long)]
+    pub license: Option<License>,
+    /// video can be embedded on other websites
+    #[clap(long)]
+    pub embeddable: Option<bool>,
+    /// extended video statistics on the watch page are visible to everyone
+    #[clap(long)]
+    pub public_stats_viewable: Option<bool>,
+    /// video is made for kids, new uploads default to false
+    #[clap(long)]
+    pub made_for_kids: Option<bool>,
+    /// video contains realistic altered or synthetic content, e.g. AI generated
+    #[clap(long)]
+    pub contains_synthetic_media: Option<bool>,
+}
+
+impl StatusOptions {
+    /// true if any status setting was given
+    pub fn is_set(&self) -> bool {
+        self.license.is_some()
+            || self.embeddable.is_some()
+            || self.public_stats_viewable.is_some()
+            || self.made_for_kids.is_some()
+            || self.contains_synthetic_media.is_some()
+    }
 }
 
 #[derive(Debug, clap::Parser)]
@@ -172,6 +508,15 @@ pub(crate) struct ListOptions {
     /// Shows a list of available methods to compute publish date
     #[clap(long)]
     pub publish_methods: bool,
+    /// List video categories of a region
+    #[clap(long)]
+    pub categories: bool,
+    /// region (ISO 3166-1 alpha-2 country code) to list categories for
+    #[clap(long, default_value = "US")]
+    pub region: String,
+    /// fetch the categories again instead of using the cached list
+    #[clap(long)]
+    pub refresh: bool,
 }
 
 #[derive(Debug, clap::Parser)]
@@ -184,6 +529,16 @@ pub(crate) struct UpdateOptions {
     /// uploads new thumbnail to youtube
     #[clap(long)]
     pub generate_thumbnail: Option<PathBuf>,
+    /// regex splitting the youtube title into placeholders for the thumbnail text, the group
+    /// named ep has to match the beginning of the video filename
+    #[clap(
+        long,
+        default_value = r"^(?P<ep>[0-9A-Fa-f]+)\.\s*(?P<name>.*?)(?:\s+-\s+(?P<series>.*))?$"
+    )]
+    pub title_pattern: Regex,
+    /// thumbnail text, placeholders are the named groups of title-pattern
+    #[clap(long, default_value = "{name}")]
+    pub thumb_template: String,
     /// thumbnail watermark file to use, will be placed ontop of screenshot
     #[clap(long, default_value = "logos.png")]
     pub thumbnail_watermark: PathBuf,
@@ -193,75 +548,510 @@ pub(crate) struct UpdateOptions {
     /// the description text of all uploaded Videos
     #[clap(long, default_value = "append")]
     pub change_desc: ChangeMode,
+    /// replace all matches of REGEX in the descriptions, REPLACEMENT may use $1 or ${name} for
+    /// groups. Only videos whose description changes are updated
+    #[clap(long, num_args = 2, value_names = ["REGEX", "REPLACEMENT"])]
+    pub desc_replace: Option<Vec<String>>,
+    /// name of a description section delimited by `--- NAME ---` and `--- end NAME ---`
+    /// lines, the section is replaced or appended with the content of --from
+    #[clap(long, requires = "from")]
+    pub section: Option<String>,
+    /// file with the content of the section
+    #[clap(long)]
+    pub from: Option<PathBuf>,
+    /// remove the description section with this name
+    #[clap(long, conflicts_with = "section")]
+    pub remove_section: Option<String>,
+    /// don't ask for confirmation before updating
+    #[clap(long, short = 'y')]
+    pub yes: bool,
+    /// continue a batch interrupted by an exceeded quota, use the same command line otherwise
+    #[clap(long)]
+    pub resume: bool,
     /// auto-create thumbnail from video at this second
     #[clap(long, default_value = "360")]
     pub thumb_second: usize,
     /// add video to playlist with given id
     #[clap(long)]
     pub add_to_playlist: Option<String>,
+    #[clap(flatten)]
+    pub status: StatusOptions,
+    #[clap(flatten)]
+    pub metadata: MetadataOptions,
+    /// only print the changes, nothing is updated
+    #[clap(long)]
+    pub dry_run: bool,
     /// path to ffmpeg binary
     #[clap(long, default_value = "ffmpeg")]
     pub ffmpeg_bin: PathBuf,
 }
 
+/// snippet, status and localization changes of existing videos, unset ones are left as they are
+#[derive(Debug, clap::Args)]
+pub(crate) struct MetadataOptions {
+    /// new title
+    #[clap(long)]
+    pub title: Option<String>,
+    /// replace all tags, comma separated
+    #[clap(long, value_delimiter = ',')]
+    pub tags: Option<Vec<String>>,
+    /// tags to add, comma separated
+    #[clap(long, value_delimiter = ',')]
+    pub add_tags: Vec<String>,
+    /// tags to remove, comma separated
+    #[clap(long, value_delimiter = ',')]
+    pub remove_tags: Vec<String>,
+    /// category name or ID, see `list --categories`
+    #[clap(long)]
+    pub category: Option<String>,
+    /// region the category is looked up in
+    #[clap(long, default_value = "US")]
+    pub region: String,
+    /// language of title and description, also set as audio language, e.g. en
+    #[clap(long)]
+    pub language: Option<String>,
+    /// privacy status
+    #[clap(long)]
+    pub privacy_status: Option<PrivacyStates>,
+    /// new publish date-time e.g. 2021-10-01T08:00:00, the video is made private.
+    /// "none" removes the publish date
+    #[clap(long)]
+    pub publish_at: Option<String>,
+    /// time zone of publish-at, e.g. Europe/Vienna
+    #[clap(long, env = "RSYTUP_TIMEZONE", default_value = "UTC")]
+    pub timezone: Tz,
+    /// localized title <language>=<title>, can be given multiple times.
+    /// the video needs a default language, see --language
+    #[clap(long, value_parser = parse_localized)]
+    pub localized_title: Vec<(String, String)>,
+    /// localized description <language>=<file>, can be given multiple times
+    #[clap(long, value_parser = parse_localized)]
+    pub localized_description: Vec<(String, String)>,
+}
+
+impl MetadataOptions {
+    /// true if any metadata change was given
+    pub fn is_set(&self) -> bool {
+        self.title.is_some()
+            || self.tags.is_some()
+            || !self.add_tags.is_empty()
+            || !self.remove_tags.is_empty()
+            || self.category.is_some()
+            || self.language.is_some()
+            || self.privacy_status.is_some()
+            || self.publish_at.is_some()
+            || !self.localized_title.is_empty()
+            || !self.localized_description.is_empty()
+    }
+
+    /// publish date in UTC, `Some(None)` removes the publish date
+    pub fn publish_datetime(&self) -> anyhow::Result<Option<Option<String>>> {
+        match self.publish_at.as_deref() {
+            None => Ok(None),
+            Some("none") => Ok(Some(None)),
+            Some(datetime) => {
+                let local = date_compute::parse_iso_datetime(datetime)?;
+                Ok(Some(Some(date_compute::format_rfc3339(
+                    date_compute::to_utc(local, &self.timezone),
+                ))))
+            }
+        }
+    }
+
+    /// tags after replacing, adding and removing
+    pub fn edit_tags(&self, tags: &[String]) -> Vec<String> {
+        let mut tags = self.tags.clone().unwrap_or_else(|| tags.to_vec());
+        for tag in &self.add_tags {
+            if !tags.contains(tag) {
+                tags.push(tag.clone());
+            }
+        }
+        tags.retain(|t| !self.remove_tags.contains(t));
+        tags
+    }
+}
+
+#[derive(Debug, clap::Parser)]
+pub(crate) struct BackupOptions {
+    /// archive file, defaults to backup-<date>.json
+    #[clap(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, clap::Parser)]
+pub(crate) struct RestoreOptions {
+    /// archive file written by backup
+    #[clap(long)]
+    pub from: PathBuf,
+    /// fields to restore, comma separated, all if not given
+    #[clap(long, value_delimiter = ',')]
+    pub fields: Vec<RestoreField>,
+    /// only restore this video
+    #[clap(long)]
+    pub video_id: Option<String>,
+    /// only print the changes, nothing is updated
+    #[clap(long)]
+    pub dry_run: bool,
+    /// don't ask for confirmation before updating
+    #[clap(long, short = 'y')]
+    pub yes: bool,
+}
+
+impl RestoreOptions {
+    pub fn fields(&self) -> Vec<RestoreField> {
+        if self.fields.is_empty() {
+            <RestoreField as clap::ValueEnum>::value_variants().to_vec()
+        } else {
+            self.fields.clone()
+        }
+    }
+}
+
+#[cfg(feature = "fake-api")]
+#[derive(Debug, clap::Parser)]
+pub(crate) struct FakeApiOptions {
+    /// port on 127.0.0.1, 0 picks a free one
+    #[clap(long, default_value = "8080")]
+    pub port: u16,
+    /// number of sample videos in the uploads playlist
+    #[clap(long, default_value = "0")]
+    pub videos: usize,
+    /// empty playlists to create
+    #[clap(long)]
+    pub playlist: Vec<String>,
+}
+
+#[derive(Debug, clap::Parser)]
+pub(crate) struct AuthOptions {
+    #[clap(subcommand)]
+    pub cmd: AuthCommand,
+}
+
+#[derive(Debug, clap::Parser)]
+pub(crate) enum AuthCommand {
+    /// Authorize rsytup ahead of time and cache the tokens
+    Login(AuthLoginOptions),
+    /// Show the authorized channel, granted scopes and token expiry
+    Status,
+    /// Revoke and remove the cached tokens
+    Logout,
+}
+
+#[derive(Debug, clap::Parser)]
+pub(crate) struct AuthLoginOptions {
+    /// scopes to request, comma separated
+    #[clap(long, value_delimiter = ',', default_value = "upload,force-ssl")]
+    pub scopes: Vec<Scope>,
+}
+
+#[derive(Debug, clap::Parser)]
+pub(crate) struct BrandingOptions {
+    #[clap(subcommand)]
+    pub cmd: BrandingCommand,
+}
+
+#[derive(Debug, clap::Parser)]
+pub(crate) enum BrandingCommand {
+    /// In-video watermark shown on all videos of the channel
+    #[clap(subcommand)]
+    Watermark(WatermarkCommand),
+}
+
+#[derive(Debug, clap::Parser)]
+pub(crate) enum WatermarkCommand {
+    /// Upload an image and set it as watermark
+    Set(WatermarkSetOptions),
+    /// Remove the watermark
+    Unset,
+}
+
+#[derive(Debug, clap::Parser)]
+pub(crate) struct WatermarkSetOptions {
+    /// watermark image, square PNG or GIF of at least 150x150 pixels
+    #[clap(long)]
+    pub image: PathBuf,
+    /// when the watermark appears, format <method>=<duration>
+    /// methods are offset-from-start and offset-from-end, duration e.g. 1500ms, 30s or 2m
+    #[clap(long, default_value = "offset-from-start=0s")]
+    pub timing: WatermarkTiming,
+    /// how long the watermark is shown, whole video if not given
+    #[clap(long, value_parser = parse_duration_ms)]
+    pub duration: Option<u64>,
+    /// corner of the video the watermark is shown in
+    #[clap(long, default_value = "bottom-right")]
+    pub corner: Corner,
+    /// channel the watermark links to, defaults to your channel
+    #[clap(long)]
+    pub target_channel_id: Option<String>,
+}
+
+#[derive(Debug, clap::Parser)]
+pub(crate) struct ScheduleOptions {
+    #[clap(subcommand)]
+    pub cmd: ScheduleCommand,
+}
+
+#[derive(Debug, clap::Parser)]
+pub(crate) enum ScheduleCommand {
+    /// Print upcoming publish slots and which of them are taken
+    Show(ScheduleShowOptions),
+}
+
+#[derive(Debug, clap::Parser)]
+pub(crate) struct ScheduleShowOptions {
+    /// cadence rule, e.g. weekly:tue,fri, biweekly:mon or monthly:first-wed
+    #[clap(long)]
+    pub rule: Rule,
+    /// dates never to publish at, comma separated ISO dates or ranges <from>..<to>
+    #[clap(long, value_delimiter = ',')]
+    pub blackout: Vec<String>,
+    /// Date of First episode, biweekly rules count weeks from here
+    #[clap(long, default_value = "2020-09-01")]
+    pub first_episode_date: String,
+    /// number of slots to show
+    #[clap(long, default_value = "10")]
+    pub count: usize,
+    /// time zone of the calendar, e.g. Europe/Vienna
+    #[clap(long, env = "RSYTUP_TIMEZONE", default_value = "UTC")]
+    pub timezone: Tz,
+}
+
+impl ScheduleShowOptions {
+    pub fn calendar(&self, scheduled: Vec<(NaiveDate, String)>) -> anyhow::Result<Calendar> {
+        Ok(Calendar {
+            rule: self.rule.clone(),
+            anchor: date_compute::parse_iso_date(&self.first_episode_date)?,
+            blackout: schedule::parse_blackout(&self.blackout)?,
+            scheduled,
+        })
+    }
+}
+
 impl UploadOptions {
     pub fn tags(&self) -> Vec<String> {
         self.keywords.split(',').map(String::from).collect()
     }
 
-    pub fn publish_datetime(&self) -> anyhow::Result<String> {
-        let today = chrono::offset::Local::now().naive_local().date();
-        match &self.publish_at {
-            PublishDate::Asap => Ok(format!("{:?}T00:00:00Z", today)),
-            PublishDate::Coming(wd) => Ok(format!(
-                "{:?}T{}Z",
-                date_compute::coming_weekday(today, wd.to_owned().parse()?),
-                self.publish_time
-            )),
-            PublishDate::WeeksFromEpisode => Ok(format!(
-                "{:?}T{}Z",
-                date_compute::add_weeks(
-                    date_compute::parse_iso_date(&self.first_episode_date)?,
-                    self.episode_nr()?,
-                ),
-                self.publish_time
-            )),
-            PublishDate::IsoDate(date) => Ok(format!(
-                "{:?}T{}Z",
-                date_compute::parse_iso_date(date)?,
-                self.publish_time
-            )),
-            PublishDate::IsoDateTime(datetime) => Ok(format!(
-                "{:?}Z",
-                date_compute::parse_iso_datetime(datetime)?
-            )),
+    /// publish date and time as UTC timestamp, dates and times are read as wall-clock time of
+    /// the configured time zone. None if the video is published without schedule.
+    pub fn publish_datetime(&self) -> anyhow::Result<Option<String>> {
+        if self.after_processing {
+            return Ok(None);
+        }
+        let today = date_compute::today(&self.timezone);
+        let time = date_compute::parse_iso_time(&self.publish_time)?;
+        let local = match &self.publish_at {
+            PublishDate::Asap => return Ok(None),
+            PublishDate::Coming(wd) => {
+                date_compute::coming_weekday(today, wd.to_owned().parse()?).and_time(time)
+            }
+            PublishDate::WeeksFromEpisode => date_compute::add_weeks(
+                date_compute::parse_iso_date(&self.first_episode_date)?,
+                self.episode_nr()?,
+            )
+            .and_time(time),
+            PublishDate::IsoDate(date) => date_compute::parse_iso_date(date)?.and_time(time),
+            PublishDate::IsoDateTime(datetime) => date_compute::parse_iso_datetime(datetime)?,
+            PublishDate::Rule(rule) => self
+                .calendar(rule, vec![])?
+                .next_free(today)?
+                .and_time(time),
+            PublishDate::NextFreeSlot(rule) => self
+                .calendar(rule, self.scheduled.clone())?
+                .next_free(today)?
+                .and_time(time),
+        };
+        Ok(Some(date_compute::format_rfc3339(date_compute::to_utc(
+            local,
+            &self.timezone,
+        ))))
+    }
+
+    /// privacy status of the video once published, private ones can't be published
+    pub fn published_privacy(&self) -> PrivacyStates {
+        match self.privacy_status {
+            PrivacyStates::Private => PrivacyStates::Public,
+            privacy => privacy,
         }
     }
 
+    /// privacy status set when uploading
+    pub fn upload_privacy(&self) -> PrivacyStates {
+        if self.after_processing {
+            PrivacyStates::Private
+        } else if let PublishDate::Asap = self.publish_at {
+            self.published_privacy()
+        } else {
+            self.privacy_status
+        }
+    }
+
+    fn calendar(
+        &self,
+        rule: &str,
+        scheduled: Vec<(NaiveDate, String)>,
+    ) -> anyhow::Result<Calendar> {
+        Ok(Calendar {
+            rule: rule.parse()?,
+            anchor: date_compute::parse_iso_date(&self.first_episode_date)?,
+            blackout: schedule::parse_blackout(&self.blackout)?,
+            scheduled,
+        })
+    }
+
+    /// loudness normalization target, if measuring or normalizing was requested
+    pub fn loudnorm_target(&self) -> Option<LoudnormTarget> {
+        if self.loudnorm.is_none() && !self.measure_loudness {
+            return None;
+        }
+        Some(LoudnormTarget {
+            integrated: self.loudnorm.unwrap_or(-14.0),
+            true_peak: self.loudnorm_true_peak,
+            lra: self.loudnorm_lra,
+        })
+    }
+
     /// if title is given use it, otherwise create from filename
     pub fn title(&self) -> String {
-        if self.title.is_some() {
-            self.title.as_deref().unwrap().to_string()
-        } else {
-            self.file
-                .clone()
+        match &self.title {
+            Some(title) => title.clone(),
+            None => self
+                .file
                 .file_stem()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .to_string()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default(),
         }
     }
 
-    /// if episode_nr is given use it, otherwise
-    /// try to convert first two chars in title from hex to u8
-    pub fn episode_nr(&self) -> anyhow::Result<u8> {
-        if self.episode_nr.is_some() {
-            Ok(self.episode_nr.unwrap())
-        } else {
-            let number = self.title().chars().take(2).collect::<String>();
-            std::primitive::u8::from_str_radix(&number, 16)
-                .map_err(|_| anyhow::anyhow!("first two digits of title should be hex-number."))
+    /// if episode_nr is given use it, otherwise find it in the title using the episode scheme
+    pub fn episode(&self) -> anyhow::Result<Episode> {
+        let mut episode = match self.episode_nr {
+            Some(number) => Episode {
+                season: None,
+                number,
+            },
+            None => self.episode_scheme.extract(&self.title())?,
+        };
+        if self.season.is_some() {
+            episode.season = self.season;
         }
+        Ok(episode)
+    }
+
+    pub fn episode_nr(&self) -> anyhow::Result<u32> {
+        Ok(self.episode()?.number)
+    }
+
+    /// placeholder values for title and thumbnail templates
+    fn template_values(&self) -> anyhow::Result<HashMap<&str, Value>> {
+        let stem = self
+            .file
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut values = match &self.filename_pattern {
+            Some(re) => template::captures(re, &stem)?,
+            None => HashMap::new(),
+        };
+        // numbers captured by the filename pattern take precedence over the episode scheme
+        for key in ["ep", "season"] {
+            if let Some(Value::Text(text)) = values.get(key) {
+                if let Ok(number) = text.parse() {
+                    values.insert(key, Value::Number(number));
+                }
+            }
+        }
+        values.insert("stem", Value::Text(stem));
+        values.insert("title", Value::Text(self.title()));
+        if let Some(series) = &self.series {
+            values.insert("series", Value::Text(series.clone()));
+        }
+        if let Ok(episode) = self.episode() {
+            values.entry("ep").or_insert(Value::Number(episode.number));
+            if let Some(season) = episode.season {
+                values.entry("season").or_insert(Value::Number(season));
+            }
+        }
+        Ok(values)
+    }
+
+    /// title as shown on youtube, rendered from the title template if one was given
+    pub fn youtube_title(&self) -> anyhow::Result<String> {
+        match &self.title_template {
+            Some(title_template) => template::render(title_template, &self.template_values()?),
+            None => Ok(self.title()),
+        }
+    }
+
+    /// text on the thumbnail, rendered from the thumbnail template if one was given
+    pub fn thumbnail_text(&self) -> anyhow::Result<String> {
+        match &self.thumb_template {
+            Some(thumb_template) => template::render(thumb_template, &self.template_values()?),
+            None => Ok(self.title()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_upload_scopes() {
+        let scopes = |args: &[&str]| {
+            let args = ["rsytup", "upload", "-f", "30. Traits.mov", "-d", "traits"]
+                .into_iter()
+                .chain(args.iter().copied());
+            Options::try_parse_from(args).unwrap().cmd.scopes()
+        };
+        assert_eq!(scopes(&[]), vec![Scope::Upload]);
+        assert_eq!(scopes(&["--wait"]), vec![Scope::Upload, Scope::Readonly]);
+        assert_eq!(
+            scopes(&["--after-processing"]),
+            vec![Scope::Upload, Scope::ForceSsl]
+        );
+        assert_eq!(
+            scopes(&["--wait", "--after-processing", "--playlist-id", "PLrust"]),
+            vec![Scope::Upload, Scope::Readonly, Scope::ForceSsl]
+        );
+        assert_eq!(
+            scopes(&["--publish-at", "next-free-slot=weekly:fri"]),
+            vec![Scope::Upload, Scope::Readonly]
+        );
+    }
+
+    #[test]
+    fn test_template_values() {
+        let options = UploadOptions::try_parse_from([
+            "upload",
+            "-f",
+            "31. Traits.mov",
+            "-d",
+            "traits",
+            "--series",
+            "From Python to Rust",
+            "--filename-pattern",
+            r"^(?P<ep>\d+)\. (?P<name>.*)$",
+            "--title-template",
+            "{ep:03}. {name} - {series}",
+        ])
+        .unwrap();
+        assert_eq!(
+            options.youtube_title().unwrap(),
+            "031. Traits - From Python to Rust"
+        );
+        // without a pattern the episode scheme reads the hex prefix of the title
+        let options = UploadOptions::try_parse_from([
+            "upload",
+            "-f",
+            "31. Traits.mov",
+            "-d",
+            "traits",
+            "--title-template",
+            "{ep}. {title}",
+        ])
+        .unwrap();
+        assert_eq!(options.youtube_title().unwrap(), "49. 31. Traits");
     }
 }
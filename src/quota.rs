@@ -0,0 +1,295 @@
+//! Estimated YouTube API quota usage, kept per day in quota.json
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2021 Michael Kefeder
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+
+/// default state file in the working directory, next to tokencache.json
+pub const STATE_FILE: &str = "quota.json";
+
+/// file the usage is kept in, without one it is only tracked in memory, e.g. in tests
+static STATE_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// sets the state file, only the first call has an effect
+pub fn set_state_file(path: impl Into<PathBuf>) {
+    let _ = STATE_PATH.set(path.into());
+}
+
+/// API operations and their cost in quota units
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display)]
+pub enum Operation {
+    #[strum(serialize = "list")]
+    List,
+    #[strum(serialize = "videos.insert")]
+    VideoInsert,
+    /// sending the file to the upload session of an insert
+    #[strum(serialize = "upload")]
+    Upload,
+    #[strum(serialize = "videos.update")]
+    VideoUpdate,
+    #[strum(serialize = "thumbnails.set")]
+    ThumbnailSet,
+    #[strum(serialize = "playlistItems.insert")]
+    PlaylistInsert,
+    #[strum(serialize = "watermarks.set")]
+    WatermarkSet,
+    #[strum(serialize = "watermarks.unset")]
+    WatermarkUnset,
+}
+
+impl Operation {
+    pub const fn cost(self) -> u32 {
+        match self {
+            Operation::Upload => 0,
+            Operation::List => 1,
+            Operation::VideoInsert => 1600,
+            Operation::VideoUpdate
+            | Operation::ThumbnailSet
+            | Operation::PlaylistInsert
+            | Operation::WatermarkSet
+            | Operation::WatermarkUnset => 50,
+        }
+    }
+}
+
+/// videos of an interrupted batch, kept per command until they are done
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Batch {
+    /// command line of the batch, a resume has to use the same one
+    pub command: String,
+    pub pending: Vec<String>,
+}
+
+/// quota used on one day
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    /// ISO date, the quota resets at midnight Pacific Time
+    pub day: String,
+    pub used: u32,
+    /// units used per operation
+    pub operations: BTreeMap<String, u32>,
+    /// interrupted batches, at most one per command
+    #[serde(default)]
+    pub batches: Vec<Batch>,
+}
+
+/// the current quota day, quotas are reset at midnight Pacific Time
+pub fn quota_day() -> NaiveDate {
+    crate::date_compute::today(&chrono_tz::US::Pacific)
+}
+
+/// quota units left of the daily `budget`
+pub fn remaining(usage: &Usage, budget: u32) -> u32 {
+    budget.saturating_sub(usage.used)
+}
+
+impl Usage {
+    /// usage of `day`, usage of previous days is discarded, interrupted batches are kept
+    pub fn on(mut self, day: NaiveDate) -> Self {
+        let day = day.format("%Y-%m-%d").to_string();
+        if self.day != day {
+            self = Usage {
+                day,
+                batches: self.batches,
+                ..Default::default()
+            };
+        }
+        self
+    }
+
+    fn batch_mut(&mut self, command: &str) -> Option<&mut Batch> {
+        self.batches.iter_mut().find(|b| b.command == command)
+    }
+
+    pub fn record(&mut self, op: Operation) {
+        if op.cost() == 0 {
+            return;
+        }
+        self.used += op.cost();
+        *self.operations.entry(op.to_string()).or_default() += op.cost();
+    }
+}
+
+/// usage of this run, read from the state file on first use and written by `save`
+static USAGE: Mutex<Option<Usage>> = Mutex::new(None);
+
+fn read() -> Usage {
+    STATE_PATH
+        .get()
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .and_then(|json| serde_json::from_str::<Usage>(&json).ok())
+        .unwrap_or_default()
+}
+
+/// runs `f` on the usage of the current quota day
+fn with_usage<T>(f: impl FnOnce(&mut Usage) -> T) -> T {
+    let mut state = USAGE.lock().unwrap();
+    let usage = state.get_or_insert_with(read);
+    *usage = std::mem::take(usage).on(quota_day());
+    f(usage)
+}
+
+/// usage of the current quota day
+pub fn load() -> Usage {
+    with_usage(|usage| usage.clone())
+}
+
+/// writes the usage to the state file, at the end of the run and whenever a batch progresses
+pub fn save() {
+    let state = USAGE.lock().unwrap();
+    let (usage, path) = match (state.as_ref(), STATE_PATH.get()) {
+        (Some(usage), Some(path)) => (usage, path),
+        _ => return,
+    };
+    let result = serde_json::to_string_pretty(usage)
+        .map_err(anyhow::Error::from)
+        .and_then(|json| Ok(std::fs::write(path, json)?));
+    if let Err(e) = result {
+        eprintln!("warning: couldn't write {:?}: {}", path, e);
+    }
+}
+
+/// accounts for an API call, called before each request as failing ones cost quota as well
+pub fn spend(op: Operation) {
+    with_usage(|usage| usage.record(op));
+}
+
+/// refuses a batch which would use more than is left of `budget` today
+pub fn check_budget(estimate: u32, budget: u32) -> anyhow::Result<()> {
+    let usage = load();
+    let left = remaining(&usage, budget);
+    if estimate > left {
+        return Err(crate::error::Error::Quota(format!(
+            "this needs about {} quota units, but only {} of {} are left today, \
+            the quota resets at midnight Pacific Time. Use --quota-budget to override",
+            estimate, left, budget
+        ))
+        .into());
+    }
+    if estimate > left / 2 {
+        eprintln!(
+            "warning: this uses about {} of the {} quota units left today",
+            estimate, left
+        );
+    }
+    Ok(())
+}
+
+/// checks the budget for a batch of per-video operations and remembers its videos,
+/// they are removed again with `done`. an interrupted batch of the same command is replaced,
+/// those of other commands are kept
+pub fn begin(command: &str, video_ids: &[&str], per_video: u32, budget: u32) -> anyhow::Result<()> {
+    check_budget(video_ids.len() as u32 * per_video, budget)?;
+    with_usage(|usage| {
+        usage.batches.retain(|b| b.command != command);
+        usage.batches.push(Batch {
+            command: command.to_string(),
+            pending: video_ids.iter().map(|id| id.to_string()).collect(),
+        });
+    });
+    save();
+    Ok(())
+}
+
+/// video of the batch of `command` was updated, the batch is cleared when all are done
+pub fn done(command: &str, video_id: &str) {
+    with_usage(|usage| {
+        if let Some(batch) = usage.batch_mut(command) {
+            batch.pending.retain(|id| id != video_id);
+        }
+        usage.batches.retain(|b| !b.pending.is_empty());
+    });
+    save();
+}
+
+/// videos left over from an interrupted batch of the same command
+pub fn pending(command: &str) -> anyhow::Result<Vec<String>> {
+    let batches = load().batches;
+    match batches.iter().find(|b| b.command == command) {
+        Some(batch) => Ok(batch.pending.clone()),
+        None if batches.is_empty() => anyhow::bail!("there is no interrupted batch to resume"),
+        None => anyhow::bail!(
+            "there is no interrupted batch of this command, interrupted were: {}",
+            batches
+                .iter()
+                .map(|b| b.command.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// marks `video_id` of the batch of `command` as done, when the quota is exceeded the error
+/// tells how to resume the batch. only called for writes, not for dry runs
+pub fn track<T>(command: &str, video_id: &str, result: anyhow::Result<T>) -> anyhow::Result<T> {
+    match result {
+        Ok(value) => {
+            done(command, video_id);
+            Ok(value)
+        }
+        Err(e) if is_exceeded(&e) => {
+            let left = with_usage(|usage| usage.batch_mut(command).map(|b| b.pending.len()))
+                .unwrap_or_default();
+            Err(e.context(format!(
+                "daily quota exceeded, {} videos are left. Run the same command with --resume \
+                after midnight Pacific Time",
+                left
+            )))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// true if the API refused the request because the daily quota is used up
+pub fn is_exceeded(err: &anyhow::Error) -> bool {
+    crate::error::kind(err).map_or(false, |kind| kind.is_quota_exceeded())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage() {
+        let day = crate::date_compute::parse_iso_date("2021-10-01").unwrap();
+        let mut usage = Usage::default().on(day);
+        usage.record(Operation::VideoInsert);
+        usage.record(Operation::List);
+        usage.record(Operation::List);
+        assert_eq!(usage.used, 1602);
+        assert_eq!(usage.operations["list"], 2);
+        assert_eq!(remaining(&usage, 10000), 8398);
+        usage.batches.push(Batch {
+            command: "update --video-id uploaded".to_string(),
+            pending: vec!["abc".to_string()],
+        });
+        let same_day = usage.clone().on(day);
+        assert_eq!(same_day, usage);
+        assert!(usage.batch_mut("update --video-id abc").is_none());
+        let next_day = usage.on(day.succ());
+        assert_eq!(next_day.used, 0);
+        assert!(next_day.operations.is_empty());
+        assert_eq!(next_day.batches.len(), 1);
+    }
+
+    #[test]
+    fn test_is_exceeded() {
+        for reason in ["quotaExceeded", "dailyLimitExceeded"] {
+            let body = format!(r#"{{"error": {{"errors": [{{"reason": "{}"}}]}}}}"#, reason);
+            let err = anyhow::Error::from(async_google_apis_common::ApiError::HTTPResponseError(
+                hyper::StatusCode::FORBIDDEN,
+                body,
+            ));
+            assert!(is_exceeded(&err.context("upload failed")), "{}", reason);
+        }
+        let err = anyhow::Error::from(crate::error::Error::video_not_found("abc"));
+        assert!(!is_exceeded(&err));
+        assert!(!is_exceeded(&anyhow::anyhow!("quotaExceeded")));
+        let err = check_budget(20_000, 10_000).unwrap_err();
+        assert!(is_exceeded(&err));
+        assert_eq!(crate::error::exit_code(&err), 6);
+    }
+}
@@ -0,0 +1,221 @@
+//! Release calendar, computes publish dates from cadence rules
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2021 Michael Kefeder
+use crate::date_compute;
+use crate::error::Error;
+use chrono::{Datelike, NaiveDate, Weekday};
+
+/// how far into the future free slots are searched for
+const MAX_DAYS: usize = 3 * 365;
+
+/// which occurrence of a weekday within a month
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Nth {
+    Nth(u32),
+    Last,
+}
+
+/// cadence rule, format <kind>:<value>
+/// - `weekly:tue,fri` every Tuesday and Friday
+/// - `biweekly:mon` every other Monday, counted from the week of the first episode
+/// - `monthly:first-wed` first Wednesday of the month (first, second, third, fourth, last)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rule {
+    Weekly(Vec<Weekday>),
+    Biweekly(Weekday),
+    Monthly(Nth, Weekday),
+}
+
+fn parse_weekday(s: &str) -> anyhow::Result<Weekday> {
+    s.trim()
+        .parse()
+        .map_err(|_| anyhow::anyhow!("invalid weekday {:?}", s))
+}
+
+impl std::str::FromStr for Rule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, value) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow::anyhow!("rule must be formatted <kind>:<value>"))?;
+        match kind {
+            "weekly" => Ok(Rule::Weekly(
+                value
+                    .split(',')
+                    .map(parse_weekday)
+                    .collect::<anyhow::Result<_>>()?,
+            )),
+            "biweekly" => Ok(Rule::Biweekly(parse_weekday(value)?)),
+            "monthly" => {
+                let (nth, wd) = value
+                    .split_once('-')
+                    .ok_or_else(|| anyhow::anyhow!("monthly rule must be e.g. first-wed"))?;
+                let nth = match nth {
+                    "first" | "1st" => Nth::Nth(1),
+                    "second" | "2nd" => Nth::Nth(2),
+                    "third" | "3rd" => Nth::Nth(3),
+                    "fourth" | "4th" => Nth::Nth(4),
+                    "last" => Nth::Last,
+                    _ => anyhow::bail!("invalid occurrence {:?} in monthly rule", nth),
+                };
+                Ok(Rule::Monthly(nth, parse_weekday(wd)?))
+            }
+            _ => anyhow::bail!("unknown rule {:?}, use weekly, biweekly or monthly", kind),
+        }
+    }
+}
+
+impl Rule {
+    /// true if the rule publishes on `date`, `anchor` defines the weeks of biweekly rules
+    pub fn matches(&self, date: NaiveDate, anchor: NaiveDate) -> bool {
+        match self {
+            Rule::Weekly(weekdays) => weekdays.contains(&date.weekday()),
+            Rule::Biweekly(weekday) => {
+                let anchor_monday =
+                    anchor - chrono::Duration::days(anchor.weekday().num_days_from_monday() as i64);
+                let weeks = (date - anchor_monday).num_days().div_euclid(7);
+                date.weekday() == *weekday && weeks % 2 == 0
+            }
+            Rule::Monthly(nth, weekday) => {
+                date.weekday() == *weekday
+                    && match nth {
+                        Nth::Nth(n) => (date.day() - 1) / 7 + 1 == *n,
+                        Nth::Last => (date + chrono::Duration::weeks(1)).month() != date.month(),
+                    }
+            }
+        }
+    }
+}
+
+/// Parse blackout dates, each entry is an ISO date or an inclusive range `<from>..<to>`
+pub fn parse_blackout(entries: &[String]) -> anyhow::Result<Vec<NaiveDate>> {
+    let mut dates = vec![];
+    for entry in entries {
+        match entry.split_once("..") {
+            Some((from, to)) => {
+                let from = date_compute::parse_iso_date(from.trim())?;
+                let to = date_compute::parse_iso_date(to.trim())?;
+                dates.extend(from.iter_days().take_while(|d| *d <= to));
+            }
+            None => dates.push(date_compute::parse_iso_date(entry.trim())?),
+        }
+    }
+    Ok(dates)
+}
+
+/// state of a publish slot in the calendar
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Slot {
+    Free,
+    Blackout,
+    /// a video is already scheduled, holds its title
+    Taken(String),
+}
+
+/// publish slots computed from a rule, minus blackout dates and already scheduled videos
+#[derive(Debug, Clone)]
+pub struct Calendar {
+    pub rule: Rule,
+    /// reference date for biweekly rules, usually the date of the first episode
+    pub anchor: NaiveDate,
+    pub blackout: Vec<NaiveDate>,
+    /// publish dates of already scheduled videos with their title
+    pub scheduled: Vec<(NaiveDate, String)>,
+}
+
+impl Calendar {
+    /// all slots of the rule strictly after `after`
+    pub fn slots(&self, after: NaiveDate) -> impl Iterator<Item = (NaiveDate, Slot)> + '_ {
+        after
+            .iter_days()
+            .skip(1)
+            .take(MAX_DAYS)
+            .filter(|d| self.rule.matches(*d, self.anchor))
+            .map(|d| {
+                let slot = if let Some((_, title)) = self.scheduled.iter().find(|(s, _)| *s == d) {
+                    Slot::Taken(title.clone())
+                } else if self.blackout.contains(&d) {
+                    Slot::Blackout
+                } else {
+                    Slot::Free
+                };
+                (d, slot)
+            })
+    }
+
+    /// first slot after `after` which is neither blacked out nor taken
+    pub fn next_free(&self, after: NaiveDate) -> anyhow::Result<NaiveDate> {
+        self.slots(after)
+            .find(|(_, slot)| *slot == Slot::Free)
+            .map(|(d, _)| d)
+            .ok_or_else(|| {
+                Error::Schedule(format!("no free slot found for {:?}", self.rule)).into()
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(d: &str) -> NaiveDate {
+        date_compute::parse_iso_date(d).unwrap()
+    }
+
+    #[test]
+    fn test_parse_rules() {
+        assert_eq!(
+            "weekly:tue,fri".parse::<Rule>().unwrap(),
+            Rule::Weekly(vec![Weekday::Tue, Weekday::Fri])
+        );
+        assert_eq!(
+            "biweekly:monday".parse::<Rule>().unwrap(),
+            Rule::Biweekly(Weekday::Mon)
+        );
+        assert_eq!(
+            "monthly:first-wed".parse::<Rule>().unwrap(),
+            Rule::Monthly(Nth::Nth(1), Weekday::Wed)
+        );
+        assert!("daily:mon".parse::<Rule>().is_err());
+        assert!("monthly:fifth-wed".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn test_rule_matches() {
+        let anchor = date("2021-09-01");
+        let biweekly = Rule::Biweekly(Weekday::Mon);
+        assert!(!biweekly.matches(date("2021-09-06"), anchor));
+        assert!(biweekly.matches(date("2021-09-13"), anchor));
+        assert!(biweekly.matches(date("2021-08-30"), anchor));
+        let first_wed = Rule::Monthly(Nth::Nth(1), Weekday::Wed);
+        assert!(first_wed.matches(date("2021-09-01"), anchor));
+        assert!(!first_wed.matches(date("2021-09-08"), anchor));
+        let last_fri = Rule::Monthly(Nth::Last, Weekday::Fri);
+        assert!(last_fri.matches(date("2021-09-24"), anchor));
+        assert!(!last_fri.matches(date("2021-09-17"), anchor));
+    }
+
+    #[test]
+    fn test_next_free() {
+        let cal = Calendar {
+            rule: "weekly:tue,fri".parse().unwrap(),
+            anchor: date("2021-09-01"),
+            blackout: parse_blackout(&["2021-09-07..2021-09-08".to_string()]).unwrap(),
+            scheduled: vec![(date("2021-09-03"), "1. Hello".to_string())],
+        };
+        let slots: Vec<_> = cal.slots(date("2021-09-02")).take(3).collect();
+        assert_eq!(
+            slots,
+            vec![
+                (date("2021-09-03"), Slot::Taken("1. Hello".to_string())),
+                (date("2021-09-07"), Slot::Blackout),
+                (date("2021-09-10"), Slot::Free),
+            ]
+        );
+        assert_eq!(
+            cal.next_free(date("2021-09-02")).unwrap(),
+            date("2021-09-10")
+        );
+    }
+}
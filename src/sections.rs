@@ -0,0 +1,152 @@
+//! Named sections in video descriptions, delimited by `--- name ---` and `--- end name ---`
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2021 Michael Kefeder
+
+fn start_marker(name: &str) -> String {
+    format!("--- {} ---", name)
+}
+
+fn end_marker(name: &str) -> String {
+    format!("--- end {} ---", name)
+}
+
+/// line indices of the start and end marker of a section
+fn find(lines: &[&str], name: &str) -> Option<(usize, usize)> {
+    let start = lines.iter().position(|l| l.trim() == start_marker(name))?;
+    let end = lines[start..]
+        .iter()
+        .position(|l| l.trim() == end_marker(name))?;
+    Some((start, start + end))
+}
+
+/// the line ending at the end of `s`, empty if there is none
+fn line_ending(s: &str) -> &str {
+    &s[s.trim_end_matches(['\r', '\n']).len()..]
+}
+
+/// line ending used in the description, `\r\n` if any line ends with it
+fn eol(description: &str) -> &'static str {
+    if description.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    }
+}
+
+/// replaces the content of section `name`, the section is appended if it doesn't exist yet.
+/// line endings and everything outside the section are kept, so writing the same content
+/// returns the description unchanged
+pub fn upsert(description: &str, name: &str, content: &str) -> String {
+    // lines including their line ending
+    let lines: Vec<&str> = description.split_inclusive('\n').collect();
+    let eol = eol(description);
+    let body: String = content
+        .trim_end()
+        .lines()
+        .map(|line| format!("{}{}", line, eol))
+        .collect();
+    match find(&lines, name) {
+        // the marker lines are kept as they are
+        Some((from, to)) => [
+            lines[..from].concat(),
+            lines[from].to_string(),
+            body,
+            lines[to].to_string(),
+            lines[to + 1..].concat(),
+        ]
+        .concat(),
+        None => {
+            let section = format!("{}{}{}{}", start_marker(name), eol, body, end_marker(name));
+            if description.trim().is_empty() {
+                section
+            } else {
+                format!(
+                    "{}{}{}{}{}",
+                    description.trim_end(),
+                    eol,
+                    eol,
+                    section,
+                    line_ending(description)
+                )
+            }
+        }
+    }
+}
+
+/// removes section `name` including its markers, surrounding blank lines are collapsed
+pub fn remove(description: &str, name: &str) -> String {
+    let lines: Vec<&str> = description.split_inclusive('\n').collect();
+    match find(&lines, name) {
+        Some((from, to)) => {
+            let before = lines[..from].concat();
+            let after = lines[to + 1..].concat();
+            match (before.trim_end(), after.trim_start()) {
+                ("", after) => after.to_string(),
+                (before, "") => format!("{}{}", before, line_ending(description)),
+                (before, after) => format!(
+                    "{}{}{}{}",
+                    before,
+                    eol(description),
+                    eol(description),
+                    after
+                ),
+            }
+        }
+        None => description.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upsert() {
+        let desc = "about traits";
+        let with_footer = upsert(desc, "footer", "discord: old\n");
+        assert_eq!(
+            with_footer,
+            "about traits\n\n--- footer ---\ndiscord: old\n--- end footer ---"
+        );
+        let updated = upsert(&with_footer, "footer", "discord: new");
+        assert_eq!(
+            updated,
+            "about traits\n\n--- footer ---\ndiscord: new\n--- end footer ---"
+        );
+        assert_eq!(upsert(&updated, "footer", "discord: new"), updated);
+        assert_eq!(
+            upsert("", "links", "a"),
+            "--- links ---\na\n--- end links ---"
+        );
+    }
+
+    #[test]
+    fn test_remove() {
+        let desc = "about traits\n\n--- links ---\na\n--- end links ---\n\nbye";
+        assert_eq!(remove(desc, "links"), "about traits\n\nbye");
+        assert_eq!(remove(desc, "footer"), desc);
+        assert_eq!(remove("--- links ---\na\n--- end links ---", "links"), "");
+        assert_eq!(
+            remove(
+                "about traits\r\n\r\n--- links ---\r\na\r\n--- end links ---\r\n",
+                "links"
+            ),
+            "about traits\r\n"
+        );
+    }
+
+    #[test]
+    fn test_upsert_unchanged() {
+        for desc in [
+            "about traits\n\n--- footer ---\ndiscord\n--- end footer ---\n",
+            "about traits\r\n\r\n--- footer ---\r\ndiscord\r\n--- end footer ---\r\nbye \r\n",
+        ] {
+            assert_eq!(upsert(desc, "footer", "discord"), desc);
+            assert_eq!(upsert(desc, "footer", "discord\n"), desc);
+        }
+        assert_eq!(
+            upsert("about traits\r\n", "footer", "a\nb"),
+            "about traits\r\n\r\n--- footer ---\r\na\r\nb\r\n--- end footer ---\r\n"
+        );
+    }
+}
@@ -0,0 +1,108 @@
+//! Minimal `{name}` placeholder templates, used for titles and thumbnail texts
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2021 Michael Kefeder
+use regex::Regex;
+use std::collections::HashMap;
+
+/// value of a placeholder, numbers can be formatted
+#[derive(Debug, Clone)]
+pub enum Value {
+    Text(String),
+    Number(u32),
+}
+
+/// formats a number, `fmt` is an optional zero padded width followed by an optional radix
+/// `d` (decimal, default), `X` or `x` (hexadecimal), e.g. `02` or `X`
+fn format_number(n: u32, fmt: &str) -> anyhow::Result<String> {
+    let (width, radix) = fmt.split_at(fmt.find(|c: char| !c.is_ascii_digit()).unwrap_or(fmt.len()));
+    let width: usize = if width.is_empty() { 0 } else { width.parse()? };
+    let n = match radix {
+        "" | "d" => n.to_string(),
+        "X" => format!("{:X}", n),
+        "x" => format!("{:x}", n),
+        _ => anyhow::bail!("unknown number format {:?}", fmt),
+    };
+    Ok(format!("{:0>width$}", n, width = width))
+}
+
+/// replaces placeholders `{name}` or `{name:format}` by their values, `{{` and `}}` are
+/// literal braces. Using a placeholder without value is an error.
+pub fn render(template: &str, values: &HashMap<&str, Value>) -> anyhow::Result<String> {
+    let mut out = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                out.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                out.push('}');
+            }
+            '{' => {
+                let placeholder: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                let (name, fmt) = placeholder
+                    .split_once(':')
+                    .unwrap_or((placeholder.as_str(), ""));
+                match values.get(name) {
+                    Some(Value::Number(n)) => out.push_str(&format_number(*n, fmt)?),
+                    Some(Value::Text(t)) if fmt.is_empty() => out.push_str(t),
+                    Some(Value::Text(_)) => {
+                        anyhow::bail!("{{{}}} is no number, it can't be formatted", name)
+                    }
+                    None => anyhow::bail!("no value for {{{}}} in {:?}", name, template),
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    Ok(out)
+}
+
+/// values of the named groups of `re` matched against `text`, e.g. a filename or a title
+pub fn captures<'r>(re: &'r Regex, text: &str) -> anyhow::Result<HashMap<&'r str, Value>> {
+    let caps = re
+        .captures(text)
+        .ok_or_else(|| anyhow::anyhow!("{:?} does not match {:?}", text, re))?;
+    Ok(re
+        .capture_names()
+        .flatten()
+        .filter_map(|name| {
+            caps.name(name)
+                .map(|m| (name, Value::Text(m.as_str().trim().to_string())))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let values = HashMap::from([
+            ("title", Value::Text("Traits".to_string())),
+            ("ep", Value::Number(31)),
+        ]);
+        assert_eq!(render("{ep:X}. {title}", &values).unwrap(), "1F. Traits");
+        assert_eq!(render("E{ep:03} {title}", &values).unwrap(), "E031 Traits");
+        assert_eq!(render("{{{title}}}", &values).unwrap(), "{Traits}");
+        assert!(render("{series}", &values).is_err());
+        assert!(render("{title:X}", &values).is_err());
+    }
+
+    #[test]
+    fn test_captures() {
+        let re = Regex::new(r"^(?P<ep>\w+)\.\s*(?P<name>.*?)(?:\s+-\s+(?P<series>.*))?$").unwrap();
+        let values = captures(&re, "29. Pattern matching - From Python to Rust").unwrap();
+        assert_eq!(
+            render("{name} | {series} | {ep}", &values).unwrap(),
+            "Pattern matching | From Python to Rust | 29"
+        );
+        let values = captures(&re, "2A. Traits").unwrap();
+        assert_eq!(render("{name}", &values).unwrap(), "Traits");
+        assert!(render("{series}", &values).is_err());
+        assert!(captures(&re, "no episode").is_err());
+    }
+}
@@ -1,6 +1,7 @@
 //! Helpers to create a Youtube thumbnail images
 // SPDX-License-Identifier: GPL-3.0-or-later
 // Copyright © 2021 Michael Kefeder
+use crate::error::Error;
 use conv::ValueInto;
 use image::imageops::overlay;
 use image::Rgba;
@@ -9,7 +10,7 @@ use imageproc::drawing::{draw_text_mut, Canvas};
 use rusttype::{point, Font, Scale};
 
 /// Draws text centered to the image
-fn draw_centered_text<I>(image: &mut I, color: I::Pixel, text: &str)
+fn draw_centered_text<I>(image: &mut I, color: I::Pixel, text: &str) -> Result<(), Error>
 where
     I: Canvas,
     <I::Pixel as image::Pixel>::Subpixel: ValueInto<f32> + Clamp<f32>,
@@ -18,7 +19,8 @@ where
     // ATTENTION Inter-VariableFont_slnt does not work, ttf parser unwrap() panics!
     let font_data = include_bytes!("../assets/Inter-Bold.ttf");
     // This only succeeds if collection consists of one font
-    let font = Font::try_from_bytes(font_data as &[u8]).expect("Error constructing Font");
+    let font = Font::try_from_bytes(font_data as &[u8])
+        .ok_or_else(|| Error::Thumbnail("the font couldn't be loaded".to_string()))?;
 
     // The font size to use
     let scale = Scale::uniform(192.0);
@@ -33,45 +35,54 @@ where
             .collect();
         // work out the layout size
         let glyphs_height = (v_metrics.ascent - v_metrics.descent).ceil() as u32;
-        let glyphs_width = {
-            let min_x = glyphs
-                .first()
-                .map(|g| g.pixel_bounding_box().unwrap().min.x)
-                .unwrap();
-            let max_x = glyphs
-                .last()
-                .map(|g| g.pixel_bounding_box().unwrap().max.x)
-                .unwrap();
-            (max_x - min_x) as u32
+        // glyphs like spaces have no bounding box, empty lines have no width
+        let bounds: Vec<_> = glyphs
+            .iter()
+            .filter_map(|g| g.pixel_bounding_box())
+            .collect();
+        let glyphs_width = match (bounds.first(), bounds.last()) {
+            (Some(first), Some(last)) => (last.max.x - first.min.x) as u32,
+            _ => 0,
         };
         // TODO: if too wide, auto-wrap text?
-        assert!(glyphs_width < 1600);
-        assert!(y_offset + glyphs_height < image.height());
+        if glyphs_width >= 1600 || glyphs_width > image.width() {
+            return Err(Error::Thumbnail(format!("{:?} is too wide", text)));
+        }
+        if y_offset + glyphs_height >= image.height() {
+            return Err(Error::Thumbnail(format!("{:?} has too many lines", text)));
+        }
         draw_text_mut(
             image,
             color,
-            ((image.width() - glyphs_width) / 2).try_into().unwrap(),
-            y_offset.try_into().unwrap(),
+            ((image.width() - glyphs_width) / 2) as i32,
+            y_offset as i32,
             scale,
             &font,
             text,
         );
         y_offset += glyphs_height;
     }
+    Ok(())
 }
 
-pub fn make_thumbnail<P>(target: &P, background: &P, logos: &P, text: &str)
+pub fn make_thumbnail<P>(target: &P, background: &P, logos: &P, text: &str) -> Result<(), Error>
 where
     P: AsRef<std::path::Path>,
 {
-    let sb_img = image::open(&background).expect("Can't open background image.");
+    let open = |path: &P| {
+        image::open(path)
+            .map_err(|e| Error::Thumbnail(format!("{:?} can't be opened: {}", path.as_ref(), e)))
+    };
+    let sb_img = open(background)?;
 
     let mut image = sb_img.to_rgba8();
 
-    let logos = image::open(&logos).expect("Can't open logos image.");
+    let logos = open(logos)?;
     let logos = logos.to_rgba8();
-    draw_centered_text(&mut image, Rgba([227u8, 228u8, 229u8, 255u8]), text);
+    draw_centered_text(&mut image, Rgba([227u8, 228u8, 229u8, 255u8]), text)?;
     overlay(&mut image, &logos, 0, 0);
 
-    image.save(&target).unwrap();
+    image
+        .save(target)
+        .map_err(|e| Error::Thumbnail(format!("{:?} can't be written: {}", target.as_ref(), e)))
 }
@@ -2,91 +2,226 @@
 // Copyright © 2021 Michael Kefeder
 //! YouTube API connection and helper functions
 
+mod api;
+mod auth;
+pub(crate) mod backup;
+#[cfg(any(test, feature = "fake-api"))]
+mod fake;
+#[cfg(any(test, feature = "fake-api"))]
+mod fake_server;
+mod metadata;
 mod oauth_flow;
+pub(crate) mod retry;
+mod token_storage;
+mod video;
 mod youtube_v3_types;
+use api::UploadProgress;
+pub(crate) use api::YoutubeApi;
+use video::{Video, VideoStatus};
 use youtube_v3_types as yt;
 
-use crate::options::{ChangeMode, UploadOptions};
+use crate::categories::VideoCategory;
+use crate::error::Error;
+#[cfg(feature = "fake-api")]
+use crate::options::FakeApiOptions;
+use crate::options::{
+    AuthFlow, ChangeMode, LoginOptions, MetadataOptions, PrivacyStates, RestoreField, Scope,
+    StatusOptions, UploadOptions, WatermarkSetOptions, WatermarkTiming,
+};
+use crate::quota::Operation;
+use anyhow::Context;
 use async_google_apis_common as common;
+use retry::retry;
 use std::rc::Rc;
 
+/// scope of the YouTube API
+fn api_scope(scope: Scope) -> yt::YoutubeScopes {
+    match scope {
+        Scope::Readonly => yt::YoutubeScopes::YoutubeReadonly,
+        Scope::Upload => yt::YoutubeScopes::YoutubeUpload,
+        Scope::ForceSsl => yt::YoutubeScopes::YoutubeForceSsl,
+        Scope::Manage => yt::YoutubeScopes::Youtube,
+    }
+}
+
 /// Create a new HTTPS client.
 fn https_client() -> common::TlsClient {
     let conn = hyper_rustls::HttpsConnector::with_native_roots();
     hyper::Client::builder().build(conn)
 }
 
-async fn service_basics() -> (
-    hyper::Client<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>,
-    common::yup_oauth2::authenticator::Authenticator<
-        hyper_rustls::HttpsConnector<hyper::client::HttpConnector>,
-    >,
-) {
-    let https = https_client();
-    // Put your client secret in the working directory!
-    let sec = common::yup_oauth2::read_application_secret("client_secret.json")
-        .await
-        .expect("client secret couldn't be read.");
-    let auth = common::yup_oauth2::InstalledFlowAuthenticator::builder(
-        sec,
-        common::yup_oauth2::InstalledFlowReturnMethod::HTTPRedirect,
-    )
-    .persist_tokens_to_disk("tokencache.json")
-    // use our custom flow delegate instead of default
-    .flow_delegate(Box::new(oauth_flow::InstalledFlowBrowserDelegate))
-    .hyper_client(https.clone())
-    .build()
-    .await
-    .expect("InstalledFlowAuthenticator failed to build");
-
-    (https, auth)
-}
-
-pub(crate) async fn video_service() -> yt::VideosService {
-    let (https, auth) = service_basics().await;
-    let scopes = vec![
-        yt::YoutubeScopes::YoutubeUpload,
-        yt::YoutubeScopes::YoutubeForceSsl,
-    ];
-    let mut cl = yt::VideosService::new(https, Rc::new(auth));
-    cl.set_scopes(&scopes);
-    cl
-}
-
-pub async fn thumbnail_service() -> yt::ThumbnailsService {
-    let (https, auth) = service_basics().await;
-    let scopes = vec![
-        yt::YoutubeScopes::YoutubeUpload,
-        yt::YoutubeScopes::YoutubeForceSsl,
-    ];
-    let mut cl = yt::ThumbnailsService::new(https, Rc::new(auth));
-    cl.set_scopes(&scopes);
-    cl
-}
-
-pub async fn playlist_service() -> yt::PlaylistItemsService {
-    let (https, auth) = service_basics().await;
-    let scopes = vec![
-        yt::YoutubeScopes::YoutubeUpload,
-        yt::YoutubeScopes::YoutubeForceSsl,
-    ];
-    let mut cl = yt::PlaylistItemsService::new(https, Rc::new(auth));
-    cl.set_scopes(&scopes);
-    cl
-}
-
-pub async fn channels_service() -> yt::ChannelsService {
-    let (https, auth) = service_basics().await;
-    let scopes = vec![
-        yt::YoutubeScopes::YoutubeUpload,
-        yt::YoutubeScopes::YoutubeForceSsl,
-    ];
-    let mut cl = yt::ChannelsService::new(https, Rc::new(auth));
-    cl.set_scopes(&scopes);
-    cl
-}
-
-pub(crate) async fn video_list(cl: &mut yt::VideosService) {
+/// authenticated connection to the YouTube API.
+/// the secret is read and the authenticator built once, all services handed out share the
+/// authenticator and the connection pool of the HTTPS client
+pub(crate) struct YoutubeClient {
+    https: common::TlsClient,
+    auth: Rc<common::Authenticator>,
+    scopes: Vec<yt::YoutubeScopes>,
+    tokens: token_storage::Backend,
+    /// API server replacing youtube.googleapis.com
+    base_url: Option<String>,
+}
+
+/// the `--api-base-url` of the login options. tokens are only sent to servers other than YouTube
+/// when they were given with `--auth-flow token` or the server runs on this host
+fn api_base_url(login: &LoginOptions) -> anyhow::Result<Option<String>> {
+    let url = match &login.api_base_url {
+        Some(url) => url,
+        None => return Ok(None),
+    };
+    let uri: hyper::Uri = url
+        .parse()
+        .with_context(|| Error::Config(format!("invalid --api-base-url {}", url)))?;
+    let host = uri.host().unwrap_or_default();
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let loopback = host == "localhost"
+        || host
+            .parse::<std::net::IpAddr>()
+            .map_or(false, |ip| ip.is_loopback());
+    if login.auth_flow != AuthFlow::Token && !loopback {
+        return Err(Error::Config(format!(
+            "--api-base-url {} is only accepted on localhost or with --auth-flow token",
+            url
+        ))
+        .into());
+    }
+    Ok(Some(url.clone()))
+}
+
+impl YoutubeClient {
+    /// client requesting tokens for `scopes`, the tokens are requested when the first API call
+    /// needs them
+    pub async fn new(login: &LoginOptions, scopes: &[Scope]) -> anyhow::Result<Self> {
+        let https = https_client();
+        let tokens = token_storage::Backend::new(login)?;
+        let auth = auth::authenticator(login.auth_flow, tokens.clone(), https.clone()).await?;
+        Ok(Self {
+            https,
+            auth: Rc::new(auth),
+            scopes: scopes.iter().map(|s| api_scope(*s)).collect(),
+            tokens,
+            base_url: api_base_url(login)?,
+        })
+    }
+
+    /// scopes of services which are only used to read, the command scopes if they allow
+    /// reading already, otherwise a read-only token is requested in addition
+    fn read_scopes(&self) -> Vec<yt::YoutubeScopes> {
+        let readonly = yt::YoutubeScopes::YoutubeReadonly;
+        if self
+            .scopes
+            .iter()
+            .any(|s| token_storage::covers(s.as_ref(), readonly.as_ref()))
+        {
+            self.scopes.clone()
+        } else {
+            vec![readonly]
+        }
+    }
+
+    /// runs the authorization flow now unless a valid token is cached already
+    pub async fn login(&self) -> anyhow::Result<()> {
+        self.auth.token(self.scopes.as_slice()).await?;
+        Ok(())
+    }
+
+    /// prints channel, granted scopes and expiry of each cached token
+    pub async fn auth_status(&self) -> anyhow::Result<()> {
+        let tokens = self.tokens.load()?;
+        if tokens.is_empty() {
+            println!("not logged in, run: rsytup auth login");
+            return Ok(());
+        }
+        println!("{}", channel_status(self, &tokens).await?);
+        for cached in tokens {
+            // refreshes the token if it expired already
+            let token = self.auth.token(cached.scopes.as_slice()).await?;
+            let status = auth::token_status(&self.https, token.as_str()).await?;
+            for scope in status.scope.split_whitespace() {
+                println!("scope: {}", scope);
+            }
+            println!("access token expires in {}s", status.expires_in);
+        }
+        Ok(())
+    }
+
+    /// revokes the cached tokens at Google and removes them.
+    /// the local tokens are removed even if revoking some of them failed
+    pub async fn logout(&self) -> anyhow::Result<()> {
+        let mut failed = 0;
+        for cached in self.tokens.load()? {
+            let token = cached
+                .token
+                .refresh_token
+                .unwrap_or(cached.token.access_token);
+            match auth::revoke(&self.https, &token).await {
+                Ok(()) => println!("revoked token for {}", cached.scopes.join(" ")),
+                Err(e) => {
+                    eprintln!(
+                        "warning: couldn't revoke token for {}: {:#}",
+                        cached.scopes.join(" "),
+                        e
+                    );
+                    failed += 1;
+                }
+            }
+        }
+        if let token_storage::Backend::Env = self.tokens {
+            println!(
+                "tokens from {} revoked, remove the secret from your CI",
+                self.tokens
+            );
+        } else if self.tokens.clear()? {
+            println!("removed tokens from {}", self.tokens);
+        }
+        if failed > 0 {
+            return Err(Error::Auth(format!(
+                "{} tokens couldn't be revoked, remove the app at \
+                https://myaccount.google.com/permissions",
+                failed
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    /// watermarks are set with the generated service, it only talks to YouTube
+    pub fn watermarks(&self) -> anyhow::Result<yt::WatermarksService> {
+        if self.base_url.is_some() {
+            return Err(
+                Error::Config("watermarks can't be set with --api-base-url".to_string()).into(),
+            );
+        }
+        let mut cl = yt::WatermarksService::new(self.https.clone(), self.auth.clone());
+        cl.set_scopes(&self.scopes);
+        Ok(cl)
+    }
+}
+
+/// serves a fake channel with `videos` samples and the empty `playlist`s until interrupted
+#[cfg(feature = "fake-api")]
+pub(crate) async fn serve_fake_api(options: &FakeApiOptions) -> anyhow::Result<()> {
+    let api = std::sync::Arc::new(fake_server::FakeApi::default());
+    for n in 1..=options.videos {
+        api.youtube.add_video(
+            &format!("sample{:05}", n),
+            &format!("{}. Sample Video", n),
+            "a sample video of the fake API",
+        );
+    }
+    for playlist_id in &options.playlist {
+        api.youtube.add_playlist(playlist_id);
+    }
+    let (addr, server) = fake_server::bind(api, options.port)?;
+    println!("fake YouTube API listening on http://{}/", addr);
+    println!(
+        "use it with: RSYTUP_ACCESS_TOKEN=fake rsytup --auth-flow token --api-base-url http://{}/ <command>",
+        addr
+    );
+    Ok(server.await?)
+}
+
+pub(crate) async fn video_list(api: &impl YoutubeApi) -> anyhow::Result<()> {
     // By default, list most popular videos
     let general_params = yt::YoutubeParams {
         fields: Some("*".to_string()),
@@ -99,48 +234,80 @@ pub(crate) async fn video_list(cl: &mut yt::VideosService) {
         ..Default::default()
     };
 
-    let resp = cl.list(&p).await.expect("listing your yt failed!");
+    let resp = retry!(Operation::List, api.list_videos(&p))?;
     if let Some(videos) = resp.items {
         for f in videos {
             println!(
                 "{} => duration: {} title: '{}'",
-                f.id.unwrap(),
+                f.id.unwrap_or_default(),
                 f.content_details
-                    .map(|cd| cd.duration.unwrap_or_else(|| "n.a.".to_string()))
-                    .unwrap(),
+                    .and_then(|cd| cd.duration)
+                    .unwrap_or_else(|| "n.a.".to_string()),
                 f.snippet
-                    .map(|s| s.title.unwrap_or_else(|| "n.a.".to_string()))
-                    .unwrap()
+                    .and_then(|s| s.title)
+                    .unwrap_or_else(|| "n.a.".to_string())
             );
         }
     }
+    Ok(())
+}
+
+/// video categories available in a region
+pub(crate) async fn video_categories(
+    client: &YoutubeClient,
+    region: &str,
+) -> anyhow::Result<Vec<VideoCategory>> {
+    let params = yt::VideoCategoriesListParams {
+        part: "snippet".into(),
+        region_code: Some(region.to_string()),
+        ..Default::default()
+    };
+    let resp = retry!(Operation::List, client.list_video_categories(&params))?;
+    Ok(resp
+        .items
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|c| {
+            let snippet = c.snippet?;
+            Some(VideoCategory {
+                id: c.id?,
+                title: snippet.title.unwrap_or_default(),
+                assignable: snippet.assignable.unwrap_or(false),
+            })
+        })
+        .collect())
 }
 
 /// Upload a local file to your YouTube channel.
+/// `video_fn` may differ from `options.file` when the video was processed before upload
 pub(crate) async fn upload_file(
-    cl: &mut yt::VideosService,
+    api: &impl YoutubeApi,
     options: &UploadOptions,
+    video_fn: &std::path::Path,
 ) -> anyhow::Result<String> {
     let general_params = yt::YoutubeParams {
         fields: Some("*".to_string()),
         ..Default::default()
     };
     let vsnip = yt::VideoSnippet {
-        title: Some(options.title()),
+        title: Some(options.youtube_title()?),
         description: Some(options.description.clone()),
         tags: Some(options.tags()),
-        category_id: Some((options.category as u8).to_string()),
+        category_id: Some(options.category.clone()),
         default_language: Some("en".to_string()),
         default_audio_language: Some("en".to_string()),
         ..Default::default()
     };
-    let vstatus = yt::VideoStatus {
-        privacy_status: Some(options.privacy_status.to_string()),
-        publish_at: Some(options.publish_datetime().unwrap()),
+    let mut vstatus = VideoStatus::from(yt::VideoStatus {
+        privacy_status: Some(options.upload_privacy().to_string()),
+        publish_at: options
+            .publish_datetime()
+            .context(Error::Schedule("no publish date".to_string()))?,
         self_declared_made_for_kids: Some(false),
         ..Default::default()
-    };
-    let video = yt::Video {
+    });
+    apply_status_options(&mut vstatus, &options.status);
+    let video = Video {
         snippet: Some(vsnip),
         status: Some(vstatus),
         ..Default::default()
@@ -150,19 +317,301 @@ pub(crate) async fn upload_file(
         part: "id,status,snippet".into(),
         ..Default::default()
     };
-    let resumable = cl.insert_resumable_upload(&params, &video).await?;
-    let tf = tokio::fs::OpenOptions::new()
-        .read(true)
-        .open(options.file.clone())
-        .await?;
-    let resp = resumable.upload_file(tf).await?;
+    // an interrupted upload continues in the same session, a new one would be a new video
+    let session = retry!(
+        Operation::VideoInsert,
+        api.start_video_upload(&params, &video)
+    )?;
+    let resp = retry!(Operation::Upload, send_file(api, &session, video_fn))?;
     println!("Video-ID: {:?}, Resp:{:?}", resp.id.as_ref(), resp);
-    Ok(String::from(resp.id.as_ref().unwrap()))
+    resp.id
+        .ok_or_else(|| Error::invalid_response("the uploaded video has no id").into())
+}
+
+/// sends the file to a resumable upload session, starting after the bytes the session
+/// received already
+async fn send_file(
+    api: &impl YoutubeApi,
+    session: &str,
+    file: &std::path::Path,
+) -> anyhow::Result<Video> {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+    let mut tf = tokio::fs::File::open(file).await?;
+    let total = tf.metadata().await?.len();
+    if total == 0 {
+        return Err(Error::Media(format!("{:?} is empty", file)).into());
+    }
+    let mut progress = api.upload_status(session, total).await?;
+    loop {
+        let start = match progress {
+            UploadProgress::Done(video) => return Ok(video),
+            UploadProgress::Received(received) => received,
+        };
+        let len = api.upload_chunk_size().min(total.saturating_sub(start));
+        if len == 0 {
+            return Err(Error::invalid_response(
+                "the upload session has the whole file but no video",
+            )
+            .into());
+        }
+        let mut chunk = vec![0; len as usize];
+        tf.seek(std::io::SeekFrom::Start(start)).await?;
+        tf.read_exact(&mut chunk).await?;
+        progress = api.upload_chunk(session, start, chunk, total).await?;
+        if let UploadProgress::Received(received) = progress {
+            if received <= start {
+                return Err(Error::invalid_response("the upload session made no progress").into());
+            }
+        }
+    }
+}
+
+/// human readable processing progress, e.g. `processing: processing 12/40 parts (30%), ~2m 10s left`
+fn processing_progress(
+    status: &str,
+    progress: Option<&yt::VideoProcessingDetailsProcessingProgress>,
+) -> String {
+    let mut line = format!("processing: {}", status);
+    if let Some(progress) = progress {
+        let parse = |v: &Option<String>| v.as_deref().and_then(|v| v.parse::<u64>().ok());
+        if let (Some(done), Some(total)) = (
+            parse(&progress.parts_processed),
+            parse(&progress.parts_total),
+        ) {
+            if total > 0 {
+                line.push_str(&format!(
+                    " {}/{} parts ({}%)",
+                    done,
+                    total,
+                    100 * done / total
+                ));
+            }
+        }
+        if let Some(ms) = parse(&progress.time_left_ms) {
+            let secs = ms / 1000;
+            line.push_str(&format!(", ~{}m {}s left", secs / 60, secs % 60));
+        }
+    }
+    line
+}
+
+/// polls upload and processing status of a video every 30 seconds until YouTube finished
+/// processing. A rejected or failed video is reported as error.
+pub(crate) async fn wait_for_processing(
+    api: &impl YoutubeApi,
+    video_id: &str,
+) -> anyhow::Result<()> {
+    let params = yt::VideosListParams {
+        id: Some(video_id.to_string()),
+        part: "processingDetails,status".into(),
+        ..Default::default()
+    };
+    let failed = |reason: &str, message: String| -> anyhow::Error {
+        Error::Api {
+            reason: reason.to_string(),
+            message,
+        }
+        .into()
+    };
+    loop {
+        let resp = retry!(Operation::List, api.list_videos(&params))?;
+        let video = resp
+            .items
+            .and_then(|videos| videos.into_iter().next())
+            .ok_or_else(|| Error::video_not_found(video_id))?;
+        let vstatus = video.status.unwrap_or_default();
+        match vstatus.upload_status.as_deref() {
+            Some("rejected") => {
+                return Err(failed(
+                    "uploadRejected",
+                    format!(
+                        "video {} was rejected, uploadStatus: rejected, rejectionReason: {}",
+                        video_id,
+                        vstatus.rejection_reason.as_deref().unwrap_or("n.a.")
+                    ),
+                ))
+            }
+            Some("failed") => {
+                return Err(failed(
+                    "uploadFailed",
+                    format!(
+                        "upload of video {} failed, uploadStatus: failed, failureReason: {}",
+                        video_id,
+                        vstatus.failure_reason.as_deref().unwrap_or("n.a.")
+                    ),
+                ))
+            }
+            Some("deleted") => {
+                return Err(failed(
+                    "videoDeleted",
+                    format!("video {} was deleted", video_id),
+                ))
+            }
+            _ => {}
+        }
+        let details = video.processing_details.unwrap_or_default();
+        match details.processing_status.as_deref() {
+            Some("succeeded") => {
+                println!("processing: succeeded");
+                return Ok(());
+            }
+            Some("failed") => {
+                return Err(failed(
+                    "processingFailed",
+                    format!(
+                        "processing of video {} failed, processingFailureReason: {}",
+                        video_id,
+                        details
+                            .processing_failure_reason
+                            .as_deref()
+                            .unwrap_or("n.a.")
+                    ),
+                ))
+            }
+            Some("terminated") => {
+                return Err(failed(
+                    "processingTerminated",
+                    format!("processing of video {} was terminated", video_id),
+                ))
+            }
+            status => println!(
+                "{}",
+                processing_progress(
+                    status.unwrap_or("pending"),
+                    details.processing_progress.as_ref()
+                )
+            ),
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(30)).await;
+    }
+}
+
+/// copies the given status settings
+fn apply_status_options(vstatus: &mut VideoStatus, options: &StatusOptions) {
+    if let Some(license) = options.license {
+        vstatus.license = Some(license.to_string());
+    }
+    if options.embeddable.is_some() {
+        vstatus.embeddable = options.embeddable;
+    }
+    if options.public_stats_viewable.is_some() {
+        vstatus.public_stats_viewable = options.public_stats_viewable;
+    }
+    if options.made_for_kids.is_some() {
+        vstatus.self_declared_made_for_kids = options.made_for_kids;
+    }
+    if options.contains_synthetic_media.is_some() {
+        vstatus.contains_synthetic_media = options.contains_synthetic_media;
+    }
+}
+
+/// fetches the given parts of a video.
+/// parts are always written back completely, fields missing in an update are reset to default.
+async fn fetch_video(api: &impl YoutubeApi, video_id: &str, part: &str) -> anyhow::Result<Video> {
+    let params = yt::VideosListParams {
+        id: Some(video_id.to_string()),
+        part: part.into(),
+        ..Default::default()
+    };
+    let resp = retry!(Operation::List, api.list_videos(&params))?;
+    let mut video = resp
+        .items
+        .and_then(|videos| videos.into_iter().next())
+        .ok_or_else(|| Error::video_not_found(video_id))?;
+    writable_status(&mut video);
+    Ok(video)
+}
+
+/// madeForKids is read-only, the writable field is selfDeclaredMadeForKids
+fn writable_status(video: &mut Video) {
+    if let Some(vstatus) = video.status.as_mut() {
+        if vstatus.self_declared_made_for_kids.is_none() {
+            vstatus.self_declared_made_for_kids = vstatus.made_for_kids;
+        }
+    }
+}
+
+/// writes the given parts of a fetched and modified video
+async fn write_video(api: &impl YoutubeApi, part: &str, video: &Video) -> anyhow::Result<Video> {
+    let params = yt::VideosUpdateParams {
+        part: part.into(),
+        ..Default::default()
+    };
+    retry!(Operation::VideoUpdate, api.update_video(&params, video))
+}
+
+/// change the status of a video.
+/// like with the snippet the full status is fetched first, otherwise e.g. license and
+/// embeddable would be reset to default.
+async fn modify_status(
+    api: &impl YoutubeApi,
+    video_id: &str,
+    modify: impl FnOnce(&mut VideoStatus),
+) -> anyhow::Result<()> {
+    let video = fetch_video(api, video_id, "status").await?;
+    let mut vstatus = video
+        .status
+        .ok_or_else(|| Error::invalid_response(&format!("video {} has no status", video_id)))?;
+    modify(&mut vstatus);
+    let video = Video {
+        id: Some(video_id.to_string()),
+        status: Some(vstatus),
+        ..Default::default()
+    };
+    write_video(api, "status", &video).await?;
+    Ok(())
+}
+
+/// change privacy status of a video and remove a scheduled publish date.
+pub(crate) async fn set_privacy(
+    api: &impl YoutubeApi,
+    video_id: &str,
+    privacy: PrivacyStates,
+) -> anyhow::Result<()> {
+    modify_status(api, video_id, |vstatus| {
+        vstatus.privacy_status = Some(privacy.to_string());
+        vstatus.publish_at = None;
+    })
+    .await?;
+    println!("video {} is now {}", video_id, privacy);
+    Ok(())
+}
+
+/// change snippet, status and localizations of a video.
+/// snippet, status and localizations are fetched, modified and only the parts which actually
+/// changed are written back. with `dry_run` the changes are only printed.
+pub(crate) async fn update_metadata(
+    api: &impl YoutubeApi,
+    video_id: &str,
+    options: &MetadataOptions,
+    status: &StatusOptions,
+    category_id: Option<&str>,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    let old = fetch_video(api, video_id, "snippet,status,localizations").await?;
+    let mut new = old.clone();
+    metadata::apply(&mut new, options, category_id)?;
+    apply_status_options(new.status.get_or_insert_with(Default::default), status);
+    let changes = metadata::diff(&old, &new)?;
+    if changes.is_empty() {
+        println!("{}: unchanged", video_id);
+        return Ok(());
+    }
+    println!("{}:", video_id);
+    for change in &changes {
+        println!("  {}", change);
+    }
+    if dry_run {
+        return Ok(());
+    }
+    write_video(api, &metadata::parts(&changes), &new).await?;
+    println!("video {} updated", video_id);
+    Ok(())
 }
 
 /// Upload a Thumbnail for a videofile.
 pub(crate) async fn upload_thumbnail(
-    cl: &mut yt::ThumbnailsService,
+    api: &impl YoutubeApi,
     video_id: &str,
     thumbnail: impl AsRef<std::path::Path>,
 ) -> anyhow::Result<()> {
@@ -170,19 +619,17 @@ pub(crate) async fn upload_thumbnail(
         video_id: video_id.into(),
         ..Default::default()
     };
-    let resumable = cl.set_resumable_upload(&params).await?;
-    let tf = tokio::fs::OpenOptions::new()
-        .read(true)
-        .open(thumbnail.as_ref())
-        .await?;
-    let resp = resumable.upload_file(tf).await?;
+    let resp = retry!(
+        Operation::ThumbnailSet,
+        api.set_thumbnail(&params, thumbnail.as_ref())
+    )?;
     println!("Thumbnail-Resp:{:?}", resp);
     Ok(())
 }
 
 /// add Video to playlist
 pub(crate) async fn add_to_playlist(
-    cl: &mut yt::PlaylistItemsService,
+    api: &impl YoutubeApi,
     options: &UploadOptions,
     video_id: &str,
 ) -> anyhow::Result<()> {
@@ -192,7 +639,12 @@ pub(crate) async fn add_to_playlist(
     };
     let item = yt::PlaylistItem {
         snippet: Some(yt::PlaylistItemSnippet {
-            playlist_id: Some(options.playlist_id.as_ref().unwrap().into()),
+            playlist_id: Some(
+                options
+                    .playlist_id
+                    .clone()
+                    .ok_or_else(|| Error::Config("--playlist-id is missing".to_string()))?,
+            ),
             resource_id: Some(yt::ResourceId {
                 kind: Some("youtube#video".to_string()),
                 video_id: Some(video_id.to_string()),
@@ -202,7 +654,10 @@ pub(crate) async fn add_to_playlist(
         }),
         ..Default::default()
     };
-    let resp = cl.insert(&params, &item).await?;
+    let resp = retry!(
+        Operation::PlaylistInsert,
+        api.insert_playlist_item(&params, &item)
+    )?;
     println!("resp {:?}", resp);
     Ok(())
 }
@@ -213,7 +668,7 @@ pub(crate) async fn add_to_playlist(
 /// snippet.tags info would be reset to default! to update a snippet title and category_id are
 /// mandatory
 pub(crate) async fn change_description(
-    cl: &mut yt::VideosService,
+    api: &impl YoutubeApi,
     video_id: &str,
     description: &str,
     change_mode: ChangeMode,
@@ -223,10 +678,10 @@ pub(crate) async fn change_description(
         part: "snippet".into(),
         ..Default::default()
     };
-    let resp = cl.list(&params).await?;
-    if let Some(video) = resp.items.as_ref().unwrap().iter().take(1).next() {
-        let mut vsnip = video.snippet.as_ref().unwrap().clone();
-        let old_desc = vsnip.description.unwrap().clone();
+    let resp = retry!(Operation::List, api.list_videos(&params))?;
+    if let Some(video) = resp.items.unwrap_or_default().into_iter().next() {
+        let mut vsnip = video.snippet.unwrap_or_default();
+        let old_desc = vsnip.description.unwrap_or_default();
         let new_desc = match change_mode {
             ChangeMode::Append => format!("{}{}", old_desc.trim_end(), description),
             ChangeMode::Replace => description.to_string(),
@@ -237,43 +692,239 @@ pub(crate) async fn change_description(
             part: "id,snippet".into(),
             ..Default::default()
         };
-        let video = yt::Video {
+        let video = Video {
             id: Some(video_id.to_string()),
             snippet: Some(vsnip),
             ..Default::default()
         };
-        let resp = cl.update(&params, &video).await?;
+        let resp = retry!(Operation::VideoUpdate, api.update_video(&params, &video))?;
         println!("resp {:?}", resp);
+    } else {
+        return Err(Error::video_not_found(video_id).into());
+    }
+    Ok(())
+}
+
+/// ID of the channel belonging to the authenticated account
+pub(crate) async fn my_channel_id(api: &impl YoutubeApi) -> anyhow::Result<String> {
+    let p = yt::ChannelsListParams {
+        mine: Some(true),
+        part: "id".into(),
+        ..Default::default()
+    };
+    let resp = retry!(Operation::List, api.list_channels(&p))?;
+    resp.items
+        .and_then(|channels| channels.into_iter().next())
+        .and_then(|channel| channel.id)
+        .ok_or_else(|| Error::invalid_response("no channel found for this account").into())
+}
+
+/// title and id of the channel, looked up with a cached token which may read it.
+/// upload-only tokens would be refused, the channel isn't looked up with them
+async fn channel_status(
+    api: &impl YoutubeApi,
+    tokens: &[token_storage::CachedToken],
+) -> anyhow::Result<String> {
+    if !token_storage::can_read(tokens) {
+        return Ok("channel: unknown, the cached tokens don't allow reading it".to_string());
     }
+    let p = yt::ChannelsListParams {
+        mine: Some(true),
+        part: "snippet".into(),
+        ..Default::default()
+    };
+    let resp = retry!(Operation::List, api.list_channels(&p))?;
+    let channels: Vec<String> = resp
+        .items
+        .unwrap_or_default()
+        .into_iter()
+        .map(|channel| {
+            format!(
+                "channel: {} ({})",
+                channel.snippet.and_then(|s| s.title).unwrap_or_default(),
+                channel.id.unwrap_or_default()
+            )
+        })
+        .collect();
+    Ok(channels.join("\n"))
+}
+
+/// upload watermark image and set it as in-video branding of the channel
+pub(crate) async fn set_watermark(
+    cl: &mut yt::WatermarksService,
+    channel_id: &str,
+    options: &WatermarkSetOptions,
+) -> anyhow::Result<()> {
+    let params = yt::WatermarksSetParams {
+        channel_id: channel_id.to_string(),
+        ..Default::default()
+    };
+    let (typ, offset_ms) = match options.timing {
+        WatermarkTiming::OffsetFromStart(ms) => ("offsetFromStart", ms),
+        WatermarkTiming::OffsetFromEnd(ms) => ("offsetFromEnd", ms),
+    };
+    let branding = yt::InvideoBranding {
+        target_channel_id: options.target_channel_id.clone(),
+        timing: Some(yt::InvideoTiming {
+            typ: Some(typ.to_string()),
+            offset_ms: Some(offset_ms.to_string()),
+            duration_ms: options.duration.map(|ms| ms.to_string()),
+        }),
+        position: Some(yt::InvideoPosition {
+            typ: Some("corner".to_string()),
+            corner_position: Some(options.corner.to_string()),
+        }),
+        ..Default::default()
+    };
+    let image = tokio::fs::read(&options.image).await?;
+    retry!(
+        Operation::WatermarkSet,
+        cl.set_upload(&params, &branding, image.clone().into())
+    )?;
+    println!("watermark set for channel {}", channel_id);
+    Ok(())
+}
+
+/// remove in-video branding of the channel
+pub(crate) async fn unset_watermark(
+    cl: &mut yt::WatermarksService,
+    channel_id: &str,
+) -> anyhow::Result<()> {
+    let params = yt::WatermarksUnsetParams {
+        channel_id: channel_id.to_string(),
+        ..Default::default()
+    };
+    retry!(Operation::WatermarkUnset, cl.unset(&params))?;
+    println!("watermark removed for channel {}", channel_id);
     Ok(())
 }
 
-pub async fn uploaded_video_list(cl: &mut yt::ChannelsService) -> anyhow::Result<Vec<YtVid>> {
+pub async fn uploaded_video_list(api: &impl YoutubeApi) -> anyhow::Result<Vec<YtVid>> {
     let p = yt::ChannelsListParams {
         mine: Some(true),
         part: "contentDetails".into(),
         ..Default::default()
     };
-    let resp = cl.list(&p).await.expect("listing your yt failed!");
+    let resp = retry!(Operation::List, api.list_channels(&p))?;
     // we get the id fo the first channels playlist, pseudocode:
     // resp.items[0].content_details.related_playlists.uploads
     if let Some(channels) = resp.items {
         if let Some(channel) = channels.into_iter().take(1).next() {
             let channel_id = channel
                 .content_details
-                .unwrap()
-                .related_playlists
-                .unwrap()
-                .uploads
-                .unwrap();
+                .and_then(|cd| cd.related_playlists)
+                .and_then(|rp| rp.uploads)
+                .ok_or_else(|| Error::invalid_response("the channel has no uploads playlist"))?;
             println!("{:#?}", channel_id);
-            let mut cl = playlist_service().await;
-            return list_playlist(&mut cl, &channel_id).await;
+            return list_playlist(api, &channel_id).await;
         }
     }
     Ok(vec![])
 }
 
+/// fetches the given parts of many videos, in batches of 50 ids
+async fn fetch_videos(
+    api: &impl YoutubeApi,
+    ids: &[&str],
+    part: &str,
+) -> anyhow::Result<Vec<Video>> {
+    let mut videos = vec![];
+    // up to 50 ids per request, maxResults isn't supported together with id
+    for batch in ids.chunks(50) {
+        let params = yt::VideosListParams {
+            id: Some(batch.join(",")),
+            part: part.into(),
+            ..Default::default()
+        };
+        let resp = retry!(Operation::List, api.list_videos(&params))?;
+        for mut video in resp.items.unwrap_or_default() {
+            writable_status(&mut video);
+            videos.push(video);
+        }
+    }
+    Ok(videos)
+}
+
+/// videos with a scheduled publish date, returns (publish_at, title) pairs
+/// only private videos carry a publish date
+pub(crate) async fn scheduled_videos(
+    api: &impl YoutubeApi,
+    vids: &[YtVid],
+) -> anyhow::Result<Vec<(String, String)>> {
+    let ids: Vec<&str> = vids.iter().map(|v| v.id.as_str()).collect();
+    let mut scheduled = vec![];
+    for video in fetch_videos(api, &ids, "snippet,status").await? {
+        let publish_at = video.status.and_then(|s| s.status.publish_at);
+        let title = video.snippet.and_then(|s| s.title).unwrap_or_default();
+        if let Some(publish_at) = publish_at {
+            scheduled.push((publish_at, title));
+        }
+    }
+    Ok(scheduled)
+}
+
+/// snippet (including thumbnail URLs), status and localizations of the videos
+pub(crate) async fn backup_videos(
+    api: &impl YoutubeApi,
+    vids: &[YtVid],
+) -> anyhow::Result<Vec<Video>> {
+    let ids: Vec<&str> = vids.iter().map(|v| v.id.as_str()).collect();
+    fetch_videos(api, &ids, "snippet,status,localizations").await
+}
+
+/// puts back `fields` of the backed up videos, or only of `video_id`.
+/// the changes are printed first, `confirm` gets the number of videos to update.
+pub(crate) async fn restore_videos(
+    api: &impl YoutubeApi,
+    archive: &backup::Archive,
+    video_id: Option<&str>,
+    fields: &[RestoreField],
+    dry_run: bool,
+    confirm: impl FnOnce(usize) -> anyhow::Result<bool>,
+) -> anyhow::Result<()> {
+    let ids: Vec<&str> = archive
+        .videos
+        .iter()
+        .filter_map(|v| v.id.as_deref())
+        .filter(|id| video_id.map_or(true, |video_id| video_id == *id))
+        .collect();
+    let mut updates = vec![];
+    let now = chrono::Utc::now();
+    for current in fetch_videos(api, &ids, "snippet,status,localizations").await? {
+        let id = current.id.clone().unwrap_or_default();
+        let old = match archive.video(&id) {
+            Some(old) => old,
+            None => continue,
+        };
+        let restored = backup::restore(&current, old, fields, now);
+        let changes = metadata::diff(&current, &restored)?;
+        if changes.is_empty() {
+            continue;
+        }
+        println!("{}:", id);
+        for change in &changes {
+            println!("  {}", change);
+        }
+        updates.push((restored, metadata::parts(&changes)));
+    }
+    println!(
+        "{} of {} videos differ from the backup",
+        updates.len(),
+        ids.len()
+    );
+    if updates.is_empty() || dry_run {
+        return Ok(());
+    }
+    if !confirm(updates.len())? {
+        anyhow::bail!("aborted, nothing was restored");
+    }
+    for (video, parts) in updates {
+        write_video(api, &parts, &video).await?;
+        println!("video {} restored", video.id.as_deref().unwrap_or_default());
+    }
+    Ok(())
+}
+
 /// Youtube Video minimal information
 pub struct YtVid {
     pub id: String,
@@ -282,27 +933,25 @@ pub struct YtVid {
 }
 
 impl YtVid {
-    pub async fn from_id(cl: &mut yt::VideosService, video_id: &str) -> anyhow::Result<YtVid> {
+    pub async fn from_id(api: &impl YoutubeApi, video_id: &str) -> anyhow::Result<YtVid> {
         let params = yt::VideosListParams {
             id: Some(video_id.to_string()),
             part: "snippet".into(),
             ..Default::default()
         };
-        let resp = cl.list(&params).await?;
-        if let Some(video) = resp.items.as_ref().unwrap().iter().take(1).next() {
-            let vsnip = video.snippet.as_ref().unwrap().clone();
-            Ok(Self {
-                id: video_id.to_string(),
-                title: vsnip.title.unwrap(),
-                description: vsnip.description.unwrap(),
-            })
-        } else {
-            Ok(Self {
-                id: video_id.to_string(),
-                title: "".to_string(),
-                description: "".to_string(),
-            })
-        }
+        let resp = retry!(Operation::List, api.list_videos(&params))?;
+        let video = resp
+            .items
+            .unwrap_or_default()
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::video_not_found(video_id))?;
+        let vsnip = video.snippet.unwrap_or_default();
+        Ok(Self {
+            id: video_id.to_string(),
+            title: vsnip.title.unwrap_or_default(),
+            description: vsnip.description.unwrap_or_default(),
+        })
     }
 }
 
@@ -310,7 +959,7 @@ impl YtVid {
 /// this will loop and fetch 10 items from the list until complete
 /// returns a list of youtube videos
 pub(crate) async fn list_playlist(
-    cl: &mut yt::PlaylistItemsService,
+    api: &impl YoutubeApi,
     playlist_id: &str,
 ) -> anyhow::Result<Vec<YtVid>> {
     let mut params = yt::PlaylistItemsListParams {
@@ -321,17 +970,20 @@ pub(crate) async fn list_playlist(
     };
     let mut all_videos = vec![];
     loop {
-        let resp = cl.list(&params).await?;
+        let resp = retry!(Operation::List, api.list_playlist_items(&params))?;
         if let Some(videos) = resp.items {
             for f in videos {
-                let (video_id, title, description) = f
-                    .snippet
-                    .map(|s| {
-                        let t = s.title.unwrap_or_else(|| "n.a.".to_string());
-                        let d = s.description.unwrap_or_else(|| "n.a.".to_string());
-                        (s.resource_id.unwrap().video_id.unwrap(), t, d)
-                    })
-                    .unwrap();
+                let snippet = f.snippet.unwrap_or_default();
+                // deleted videos may be listed without the video they refer to
+                let video_id = match snippet.resource_id.and_then(|r| r.video_id) {
+                    Some(video_id) => video_id,
+                    None => {
+                        println!("skipping playlist item {:?} without video", f.id);
+                        continue;
+                    }
+                };
+                let title = snippet.title.unwrap_or_else(|| "n.a.".to_string());
+                let description = snippet.description.unwrap_or_else(|| "n.a.".to_string());
                 println!("{} => title: '{}'", video_id, title);
                 all_videos.push(YtVid {
                     id: video_id.clone(),
@@ -347,3 +999,306 @@ pub(crate) async fn list_playlist(
     }
     Ok(all_videos)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::fake::FakeYoutube;
+    use super::*;
+    use clap::Parser;
+
+    fn temp_file(name: &str, content: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("rsytup-{}-{}", std::process::id(), name));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[derive(clap::Parser)]
+    struct UpdateArgs {
+        #[clap(flatten)]
+        metadata: MetadataOptions,
+        #[clap(flatten)]
+        status: StatusOptions,
+    }
+
+    #[test]
+    fn test_api_base_url() {
+        #[derive(clap::Parser)]
+        struct LoginArgs {
+            #[clap(flatten)]
+            login: LoginOptions,
+        }
+        let login = |args: &[&str]| {
+            let args = std::iter::once("rsytup").chain(args.iter().copied());
+            LoginArgs::try_parse_from(args).unwrap().login
+        };
+        assert_eq!(api_base_url(&login(&[])).unwrap(), None);
+        for url in [
+            "http://127.0.0.1:8080/",
+            "http://[::1]:8080/",
+            "http://localhost/",
+        ] {
+            let options = login(&["--api-base-url", url]);
+            assert_eq!(api_base_url(&options).unwrap().as_deref(), Some(url));
+        }
+        let err = api_base_url(&login(&["--api-base-url", "https://example.com/"])).unwrap_err();
+        assert_eq!(crate::error::exit_code(&err), 3);
+        let options = login(&[
+            "--api-base-url",
+            "https://example.com/",
+            "--auth-flow",
+            "token",
+        ]);
+        assert_eq!(
+            api_base_url(&options).unwrap().as_deref(),
+            Some("https://example.com/")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_upload() {
+        let api = FakeYoutube::default();
+        api.add_playlist("PLrust");
+        let video_fn = temp_file("upload.mp4", b"not really a video");
+        let thumb = temp_file("thumb.png", b"not really a png");
+        let options = UploadOptions::try_parse_from([
+            "upload",
+            "--file",
+            video_fn.to_str().unwrap(),
+            "--description",
+            "about traits",
+            "--title",
+            "30. Traits",
+            "--publish-at",
+            "asap=",
+            "--playlist-id",
+            "PLrust",
+            "--license",
+            "creative-common",
+        ])
+        .unwrap();
+        // the upload is interrupted after the first chunk and continues in the same session
+        api.fail_on("upload session1 bytes 8-", 503, "backendError");
+        let video_id = upload_file(&api, &options, &video_fn).await.unwrap();
+        let video = api.video(&video_id).unwrap();
+        let vsnip = video.snippet.unwrap();
+        assert_eq!(vsnip.title.as_deref(), Some("30. Traits"));
+        assert_eq!(vsnip.description.as_deref(), Some("about traits"));
+        let vstatus = video.status.unwrap();
+        assert_eq!(vstatus.license.as_deref(), Some("creativeCommon"));
+        assert_eq!(vstatus.made_for_kids, Some(false));
+        assert_eq!(api.channel().uploads[&video_id], 18);
+        let inserts = api
+            .channel()
+            .requests
+            .iter()
+            .filter(|r| r.starts_with("videos.insert"))
+            .count();
+        assert_eq!(inserts, 1);
+        let requests = api.channel().requests.clone();
+        let uploads: Vec<&str> = requests
+            .iter()
+            .map(String::as_str)
+            .filter(|r| r.starts_with("upload "))
+            .collect();
+        assert_eq!(
+            uploads,
+            vec![
+                "upload session1 bytes */18",
+                "upload session1 bytes 0-7/18",
+                "upload session1 bytes 8-15/18",
+                "upload session1 bytes */18",
+                "upload session1 bytes 8-15/18",
+                "upload session1 bytes 16-17/18",
+            ]
+        );
+
+        upload_thumbnail(&api, &video_id, &thumb).await.unwrap();
+        assert_eq!(api.channel().thumbnails[&video_id], 16);
+        add_to_playlist(&api, &options, &video_id).await.unwrap();
+        assert_eq!(api.channel().playlists["PLrust"], vec![video_id.clone()]);
+        wait_for_processing(&api, &video_id).await.unwrap();
+        assert!(upload_thumbnail(&api, "missing", &thumb).await.is_err());
+        std::fs::remove_file(video_fn).unwrap();
+        std::fs::remove_file(thumb).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_update() {
+        let api = FakeYoutube::default();
+        api.add_video("abc", "30. Traits", "about traits");
+        let vid = YtVid::from_id(&api, "abc").await.unwrap();
+        assert_eq!(vid.title, "30. Traits");
+
+        change_description(&api, "abc", "\nbye", ChangeMode::Append)
+            .await
+            .unwrap();
+        let vsnip = api.video("abc").unwrap().snippet.unwrap();
+        assert_eq!(vsnip.description.as_deref(), Some("about traits\nbye"));
+        // the full snippet was written back
+        assert_eq!(vsnip.tags, Some(vec!["rust".to_string()]));
+
+        set_privacy(&api, "abc", PrivacyStates::Public)
+            .await
+            .unwrap();
+        let vstatus = api.video("abc").unwrap().status.unwrap();
+        assert_eq!(vstatus.privacy_status.as_deref(), Some("public"));
+        assert_eq!(vstatus.license.as_deref(), Some("youtube"));
+        assert_eq!(vstatus.embeddable, Some(true));
+
+        let args = UpdateArgs::try_parse_from([
+            "update",
+            "--title",
+            "30. Traits - From Python to Rust",
+            "--add-tags",
+            "python",
+            "--embeddable",
+            "false",
+        ])
+        .unwrap();
+        update_metadata(&api, "abc", &args.metadata, &args.status, None, true)
+            .await
+            .unwrap();
+        assert_eq!(
+            api.video("abc").unwrap().snippet.unwrap().title.as_deref(),
+            Some("30. Traits")
+        );
+        update_metadata(&api, "abc", &args.metadata, &args.status, None, false)
+            .await
+            .unwrap();
+        let video = api.video("abc").unwrap();
+        let vsnip = video.snippet.unwrap();
+        assert_eq!(
+            vsnip.title.as_deref(),
+            Some("30. Traits - From Python to Rust")
+        );
+        assert_eq!(
+            vsnip.tags,
+            Some(vec!["rust".to_string(), "python".to_string()])
+        );
+        assert_eq!(video.status.unwrap().embeddable, Some(false));
+        assert_eq!(
+            api.channel().requests.last().map(String::as_str),
+            Some("videos.update snippet,status")
+        );
+
+        assert!(UpdateArgs::try_parse_from(["update", "--localized-title", "de"]).is_err());
+        let args = UpdateArgs::try_parse_from([
+            "update",
+            "--localized-title",
+            "de=30. Traits auf Deutsch",
+        ])
+        .unwrap();
+        let err = update_metadata(&api, "abc", &args.metadata, &args.status, None, false)
+            .await
+            .unwrap_err();
+        assert_eq!(crate::error::exit_code(&err), 3);
+        let args = UpdateArgs::try_parse_from([
+            "update",
+            "--language",
+            "en",
+            "--localized-title",
+            "de=30. Traits auf Deutsch",
+        ])
+        .unwrap();
+        update_metadata(&api, "abc", &args.metadata, &args.status, None, false)
+            .await
+            .unwrap();
+        let video = api.video("abc").unwrap();
+        assert_eq!(
+            video.snippet.unwrap().default_language.as_deref(),
+            Some("en")
+        );
+        assert_eq!(
+            video.localizations.unwrap()["de"].title.as_deref(),
+            Some("30. Traits auf Deutsch")
+        );
+        assert_eq!(
+            api.channel().requests.last().map(String::as_str),
+            Some("videos.update localizations,snippet")
+        );
+
+        api.fail_next(403, "quotaExceeded");
+        let err = set_privacy(&api, "abc", PrivacyStates::Private)
+            .await
+            .unwrap_err();
+        assert!(crate::quota::is_exceeded(&err));
+        let err = set_privacy(&api, "xyz", PrivacyStates::Private)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "YouTube API error videoNotFound: video xyz not found"
+        );
+        assert_eq!(crate::error::exit_code(&err), 5);
+
+        {
+            let mut channel = api.channel();
+            let abc = channel
+                .videos
+                .iter_mut()
+                .find(|v| v.id.as_deref() == Some("abc"));
+            let vstatus = abc.unwrap().status.get_or_insert_with(Default::default);
+            vstatus.upload_status = Some("rejected".to_string());
+            vstatus.rejection_reason = Some("duplicate".to_string());
+        }
+        let err = wait_for_processing(&api, "abc").await.unwrap_err();
+        assert!(
+            err.to_string().contains("rejectionReason: duplicate"),
+            "{}",
+            err
+        );
+        assert_eq!(crate::error::exit_code(&err), 5);
+    }
+
+    #[tokio::test]
+    async fn test_channel_status() {
+        let api = FakeYoutube::default();
+        let token: common::yup_oauth2::storage::TokenInfo =
+            serde_json::from_str(r#"{"access_token": "ya29", "expires_at": null}"#).unwrap();
+        let mut tokens = vec![];
+        let upload = "https://www.googleapis.com/auth/youtube.upload";
+        token_storage::store_token(&mut tokens, &[upload], token.clone());
+        assert_eq!(
+            channel_status(&api, &tokens).await.unwrap(),
+            "channel: unknown, the cached tokens don't allow reading it"
+        );
+        assert!(api.channel().requests.is_empty());
+
+        let force_ssl = "https://www.googleapis.com/auth/youtube.force-ssl";
+        token_storage::store_token(&mut tokens, &[force_ssl], token);
+        assert_eq!(
+            channel_status(&api, &tokens).await.unwrap(),
+            "channel: Fake Channel (UCfake)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list() {
+        let api = FakeYoutube::default();
+        for i in 0..23 {
+            api.add_video(&format!("v{:02}", i), &format!("{}. Episode", i), "");
+        }
+        assert_eq!(my_channel_id(&api).await.unwrap(), "UCfake");
+        let vids = uploaded_video_list(&api).await.unwrap();
+        assert_eq!(vids.len(), 23);
+        assert_eq!(vids[0].id, "v22");
+        assert_eq!(vids[22].title, "0. Episode");
+        let pages = api
+            .channel()
+            .requests
+            .iter()
+            .filter(|r| r.starts_with("playlistItems.list"))
+            .count();
+        assert_eq!(pages, 3);
+        let videos = backup_videos(&api, &vids).await.unwrap();
+        assert_eq!(videos.len(), 23);
+        assert!(videos.iter().all(|v| v.processing_details.is_none()));
+        let params = yt::VideosListParams {
+            id: Some("v01".to_string()),
+            max_results: Some(50),
+            part: "id".into(),
+            ..Default::default()
+        };
+        assert!(api.list_videos(&params).await.is_err());
+    }
+}
@@ -0,0 +1,333 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2021 Michael Kefeder
+//! The YouTube API calls rsytup makes, implemented by the real client and the in-memory fake
+
+use super::video::{Video, VideoListResponse};
+use super::{common, yt, YoutubeClient};
+use std::fmt::Display;
+use std::path::Path;
+
+/// API server of YouTube, base of all request paths
+const DEFAULT_BASE_URL: &str = "https://youtube.googleapis.com/";
+
+/// chunk size of resumable uploads, same as the generated services
+const UPLOAD_CHUNK_SIZE: u64 = 5 * 1024 * 1024;
+
+/// progress of a resumable upload session
+#[derive(Debug)]
+pub(crate) enum UploadProgress {
+    /// number of bytes the session received so far
+    Received(u64),
+    /// the upload is complete
+    Done(Video),
+}
+
+/// videos, thumbnails, playlist items and channels of the YouTube Data API v3.
+/// errors of failed requests are `common::ApiError`s so they can be retried
+#[async_trait::async_trait(?Send)]
+pub(crate) trait YoutubeApi {
+    async fn list_videos(&self, params: &yt::VideosListParams)
+        -> anyhow::Result<VideoListResponse>;
+
+    async fn update_video(
+        &self,
+        params: &yt::VideosUpdateParams,
+        video: &Video,
+    ) -> anyhow::Result<Video>;
+
+    /// starts a resumable upload session of the video, returns the session URI.
+    /// the quota of the insert is used here, not by the upload of the file
+    async fn start_video_upload(
+        &self,
+        params: &yt::VideosInsertParams,
+        video: &Video,
+    ) -> anyhow::Result<String>;
+
+    /// asks the upload session how much of the file with `total` bytes it received
+    async fn upload_status(&self, session: &str, total: u64) -> anyhow::Result<UploadProgress>;
+
+    /// sends the bytes of the file starting at `start` to the upload session
+    async fn upload_chunk(
+        &self,
+        session: &str,
+        start: u64,
+        chunk: Vec<u8>,
+        total: u64,
+    ) -> anyhow::Result<UploadProgress>;
+
+    /// bytes sent per chunk, a multiple of 256 KiB except for the last chunk
+    fn upload_chunk_size(&self) -> u64 {
+        UPLOAD_CHUNK_SIZE
+    }
+
+    /// uploads the image in a resumable upload session
+    async fn set_thumbnail(
+        &self,
+        params: &yt::ThumbnailsSetParams,
+        image: &Path,
+    ) -> anyhow::Result<yt::ThumbnailSetResponse>;
+
+    async fn list_playlist_items(
+        &self,
+        params: &yt::PlaylistItemsListParams,
+    ) -> anyhow::Result<yt::PlaylistItemListResponse>;
+
+    async fn insert_playlist_item(
+        &self,
+        params: &yt::PlaylistItemsInsertParams,
+        item: &yt::PlaylistItem,
+    ) -> anyhow::Result<yt::PlaylistItem>;
+
+    async fn list_channels(
+        &self,
+        params: &yt::ChannelsListParams,
+    ) -> anyhow::Result<yt::ChannelListResponse>;
+}
+
+/// query string of a request, the parameters and the general attributes of any API call
+fn query(params: &impl Display, youtube_params: &Option<yt::YoutubeParams>) -> String {
+    let mut query = format!("{}", params);
+    if let Some(api_params) = youtube_params {
+        query.push_str(&api_params.to_string());
+    }
+    query
+}
+
+/// requests are made here instead of by the generated services, they don't know all fields of
+/// videos (see `video`) and can't be pointed at another API server
+impl YoutubeClient {
+    /// url of the API path below the base URL
+    fn api_url(&self, path: &str) -> String {
+        let base = self.base_url.as_deref().unwrap_or(DEFAULT_BASE_URL);
+        format!("{}/{}", base.trim_end_matches('/'), path)
+    }
+
+    /// authorization header with a token for `scopes`
+    async fn auth_headers(
+        &self,
+        scopes: &[yt::YoutubeScopes],
+    ) -> anyhow::Result<Vec<(hyper::header::HeaderName, String)>> {
+        let token = self.auth.token(scopes).await?;
+        Ok(vec![(
+            hyper::header::AUTHORIZATION,
+            format!("Bearer {}", token.as_str()),
+        )])
+    }
+
+    /// request of an API path, `query` are the parameters of the call
+    async fn request<Req, Resp>(
+        &self,
+        method: &str,
+        path: &str,
+        query: String,
+        scopes: &[yt::YoutubeScopes],
+        body: Option<&Req>,
+    ) -> anyhow::Result<Resp>
+    where
+        Req: serde::Serialize + std::fmt::Debug,
+        Resp: serde::de::DeserializeOwned + Clone + Default,
+    {
+        let url = self.api_url(path) + "?" + &query;
+        let headers = self.auth_headers(scopes).await?;
+        common::do_request(&self.https, &url, &headers, method, body).await
+    }
+
+    /// starts a resumable upload, returns the session URI
+    async fn start_upload<Req>(
+        &self,
+        path: &str,
+        query: String,
+        body: Option<&Req>,
+    ) -> anyhow::Result<String>
+    where
+        Req: serde::Serialize + std::fmt::Debug,
+    {
+        let url = self.api_url(path) + "?uploadType=resumable" + &query;
+        let headers = self.auth_headers(&self.scopes).await?;
+        let (_, resp_headers): (common::EmptyResponse, hyper::HeaderMap) =
+            common::do_request_with_headers(&self.https, &url, &headers, "POST", body).await?;
+        let session = resp_headers
+            .get(hyper::header::LOCATION)
+            .ok_or_else(|| {
+                common::ApiError::RedirectError(format!(
+                    "Resumable upload response didn't contain Location: {:?}",
+                    resp_headers
+                ))
+            })?
+            .to_str()?;
+        Ok(session.to_string())
+    }
+
+    /// PUT to a resumable upload session, answered with 308 and the received range while the
+    /// upload is incomplete
+    async fn upload_request(
+        &self,
+        session: &str,
+        content_range: String,
+        chunk: Vec<u8>,
+    ) -> anyhow::Result<UploadProgress> {
+        let req = hyper::Request::put(session)
+            .header(hyper::header::CONTENT_RANGE, content_range)
+            .header(hyper::header::CONTENT_LENGTH, chunk.len())
+            .body(hyper::Body::from(chunk))?;
+        let resp = self.https.request(req).await?;
+        let status = resp.status();
+        if status == hyper::StatusCode::PERMANENT_REDIRECT {
+            // e.g. `Range: bytes=0-524287`, missing when nothing was received yet
+            let received = resp
+                .headers()
+                .get(hyper::header::RANGE)
+                .and_then(|r| r.to_str().ok())
+                .and_then(|r| r.rsplit('-').next())
+                .and_then(|end| end.parse::<u64>().ok())
+                .map_or(0, |end| end + 1);
+            return Ok(UploadProgress::Received(received));
+        }
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        if !status.is_success() {
+            let body = String::from_utf8_lossy(&body).into_owned();
+            return Err(common::ApiError::HTTPResponseError(status, body).into());
+        }
+        Ok(UploadProgress::Done(serde_json::from_slice(&body)?))
+    }
+
+    /// video categories of a region, not part of `YoutubeApi` as the fake has fixed ones
+    pub async fn list_video_categories(
+        &self,
+        params: &yt::VideoCategoriesListParams,
+    ) -> anyhow::Result<yt::VideoCategoryListResponse> {
+        let query = query(params, &params.youtube_params);
+        let no_body: Option<&common::EmptyRequest> = None;
+        self.request(
+            "GET",
+            "youtube/v3/videoCategories",
+            query,
+            &self.read_scopes(),
+            no_body,
+        )
+        .await
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl YoutubeApi for YoutubeClient {
+    async fn list_videos(
+        &self,
+        params: &yt::VideosListParams,
+    ) -> anyhow::Result<VideoListResponse> {
+        let query = query(params, &params.youtube_params);
+        let no_body: Option<&common::EmptyRequest> = None;
+        self.request(
+            "GET",
+            "youtube/v3/videos",
+            query,
+            &self.read_scopes(),
+            no_body,
+        )
+        .await
+    }
+
+    async fn update_video(
+        &self,
+        params: &yt::VideosUpdateParams,
+        video: &Video,
+    ) -> anyhow::Result<Video> {
+        let query = query(params, &params.youtube_params);
+        self.request("PUT", "youtube/v3/videos", query, &self.scopes, Some(video))
+            .await
+    }
+
+    async fn start_video_upload(
+        &self,
+        params: &yt::VideosInsertParams,
+        video: &Video,
+    ) -> anyhow::Result<String> {
+        let query = query(params, &params.youtube_params);
+        self.start_upload("resumable/upload/youtube/v3/videos", query, Some(video))
+            .await
+    }
+
+    async fn upload_status(&self, session: &str, total: u64) -> anyhow::Result<UploadProgress> {
+        self.upload_request(session, format!("bytes */{}", total), vec![])
+            .await
+    }
+
+    async fn upload_chunk(
+        &self,
+        session: &str,
+        start: u64,
+        chunk: Vec<u8>,
+        total: u64,
+    ) -> anyhow::Result<UploadProgress> {
+        let end = start + chunk.len() as u64 - 1;
+        let range = format!("bytes {}-{}/{}", start, end, total);
+        self.upload_request(session, range, chunk).await
+    }
+
+    async fn set_thumbnail(
+        &self,
+        params: &yt::ThumbnailsSetParams,
+        image: &Path,
+    ) -> anyhow::Result<yt::ThumbnailSetResponse> {
+        let query = query(params, &params.youtube_params);
+        let no_body: Option<&common::EmptyRequest> = None;
+        let session = self
+            .start_upload("resumable/upload/youtube/v3/thumbnails/set", query, no_body)
+            .await?;
+        let resumable = common::ResumableUpload::new(
+            hyper::Uri::try_from(session)?,
+            &self.https,
+            UPLOAD_CHUNK_SIZE as usize,
+        );
+        let tf = tokio::fs::OpenOptions::new().read(true).open(image).await?;
+        resumable.upload_file(tf).await
+    }
+
+    async fn list_playlist_items(
+        &self,
+        params: &yt::PlaylistItemsListParams,
+    ) -> anyhow::Result<yt::PlaylistItemListResponse> {
+        let query = query(params, &params.youtube_params);
+        let no_body: Option<&common::EmptyRequest> = None;
+        self.request(
+            "GET",
+            "youtube/v3/playlistItems",
+            query,
+            &self.scopes,
+            no_body,
+        )
+        .await
+    }
+
+    async fn insert_playlist_item(
+        &self,
+        params: &yt::PlaylistItemsInsertParams,
+        item: &yt::PlaylistItem,
+    ) -> anyhow::Result<yt::PlaylistItem> {
+        let query = query(params, &params.youtube_params);
+        self.request(
+            "POST",
+            "youtube/v3/playlistItems",
+            query,
+            &self.scopes,
+            Some(item),
+        )
+        .await
+    }
+
+    async fn list_channels(
+        &self,
+        params: &yt::ChannelsListParams,
+    ) -> anyhow::Result<yt::ChannelListResponse> {
+        let query = query(params, &params.youtube_params);
+        let no_body: Option<&common::EmptyRequest> = None;
+        self.request(
+            "GET",
+            "youtube/v3/channels",
+            query,
+            &self.read_scopes(),
+            no_body,
+        )
+        .await
+    }
+}
@@ -0,0 +1,126 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2021 Michael Kefeder
+//! Building the OAuth2 authenticator for the selected flow.
+//! Service accounts are not offered, YouTube channels can't be owned by a service account.
+
+use super::common::{self, yup_oauth2};
+use super::oauth_flow;
+use super::token_storage::{Backend, CacheStorage};
+use crate::error::Error;
+use crate::options::AuthFlow;
+
+/// granted scopes and remaining lifetime of an access token as reported by Google
+#[derive(Debug, serde::Deserialize)]
+pub(crate) struct TokenStatus {
+    /// space separated scopes
+    #[serde(default)]
+    pub scope: String,
+    /// seconds until the token expires
+    #[serde(default)]
+    pub expires_in: String,
+}
+
+/// asks Google's tokeninfo endpoint about an access token
+pub(crate) async fn token_status(
+    https: &common::TlsClient,
+    access_token: &str,
+) -> anyhow::Result<TokenStatus> {
+    let uri = format!(
+        "https://oauth2.googleapis.com/tokeninfo?access_token={}",
+        access_token
+    );
+    let resp = https.get(uri.parse()?).await?;
+    let status = resp.status();
+    let body = hyper::body::to_bytes(resp.into_body()).await?;
+    if !status.is_success() {
+        anyhow::bail!(
+            "tokeninfo failed with HTTP {}: {}",
+            status,
+            String::from_utf8_lossy(&body)
+        );
+    }
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// revokes a token, revoking an access token revokes its refresh token as well
+pub(crate) async fn revoke(https: &common::TlsClient, token: &str) -> anyhow::Result<()> {
+    let req = hyper::Request::post("https://oauth2.googleapis.com/revoke")
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(hyper::Body::from(format!("token={}", token)))?;
+    let resp = https.request(req).await?;
+    let status = resp.status();
+    if !status.is_success() {
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        anyhow::bail!(
+            "revoking the token failed with HTTP {}: {}",
+            status,
+            String::from_utf8_lossy(&body)
+        );
+    }
+    Ok(())
+}
+
+/// authenticator of the flow, tokens are cached in the backend
+pub(crate) async fn authenticator(
+    flow: AuthFlow,
+    backend: Backend,
+    https: common::TlsClient,
+) -> anyhow::Result<common::Authenticator> {
+    let sec = if flow == AuthFlow::Token {
+        // the token is never refreshed, no client is needed
+        yup_oauth2::ApplicationSecret::default()
+    } else {
+        // Put your client secret in the working directory!
+        yup_oauth2::read_application_secret("client_secret.json")
+            .await
+            .map_err(|e| {
+                Error::Auth(format!(
+                    "client secret couldn't be read from client_secret.json: {}",
+                    e
+                ))
+            })?
+    };
+    let storage = Box::new(CacheStorage::open(backend)?);
+    let auth = match flow {
+        AuthFlow::Browser => {
+            yup_oauth2::InstalledFlowAuthenticator::builder(
+                sec,
+                yup_oauth2::InstalledFlowReturnMethod::HTTPRedirect,
+            )
+            .with_storage(storage)
+            // use our custom flow delegate instead of default
+            .flow_delegate(Box::new(oauth_flow::InstalledFlowBrowserDelegate))
+            .hyper_client(https)
+            .build()
+            .await
+        }
+        // prints the URL and reads the code pasted by the user, for env only needed if the
+        // cached tokens can't be refreshed, never for the token which doesn't expire
+        AuthFlow::Manual | AuthFlow::Env | AuthFlow::Token => {
+            yup_oauth2::InstalledFlowAuthenticator::builder(
+                sec,
+                yup_oauth2::InstalledFlowReturnMethod::Interactive,
+            )
+            .with_storage(storage)
+            .hyper_client(https)
+            .build()
+            .await
+        }
+        // prints a code to enter on google.com/device, needs a client secret of type
+        // "TVs and Limited Input devices"
+        AuthFlow::Device => {
+            yup_oauth2::DeviceFlowAuthenticator::builder(sec)
+                .with_storage(storage)
+                .hyper_client(https)
+                .build()
+                .await
+        }
+    };
+    auth.map_err(|e| {
+        Error::Auth(format!(
+            "authenticator for {} flow failed to build: {}",
+            flow, e
+        ))
+        .into()
+    })
+}
@@ -0,0 +1,222 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2021 Michael Kefeder
+//! Versioned JSON archive of video metadata
+
+use super::video::{Video, VideoStatus};
+use crate::options::RestoreField;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// archive format version, increased on incompatible changes
+pub(crate) const VERSION: u32 = 1;
+
+/// snippet, status and localizations of all videos of a channel
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Archive {
+    pub version: u32,
+    /// RFC3339 UTC time the backup was made
+    pub created_at: String,
+    pub channel_id: String,
+    pub videos: Vec<Video>,
+}
+
+impl Archive {
+    pub fn new(created_at: String, channel_id: String, videos: Vec<Video>) -> Self {
+        Self {
+            version: VERSION,
+            created_at,
+            channel_id,
+            videos,
+        }
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let archive: Self = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        if archive.version != VERSION {
+            anyhow::bail!(
+                "{:?} has archive version {}, only version {} is supported",
+                path,
+                archive.version,
+                VERSION
+            );
+        }
+        Ok(archive)
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// backed up version of a video
+    pub fn video(&self, video_id: &str) -> Option<&Video> {
+        self.videos
+            .iter()
+            .find(|v| v.id.as_deref() == Some(video_id))
+    }
+}
+
+/// the writable fields of the backed up status on top of the current status, uploadStatus,
+/// madeForKids and the other read-only fields are kept.
+/// a publish date which passed already or of a video which is public by now isn't restored,
+/// neither is the private status the video had until then
+fn restore_status(
+    current: Option<&VideoStatus>,
+    backup: &VideoStatus,
+    now: DateTime<Utc>,
+) -> VideoStatus {
+    let mut vstatus = current.cloned().unwrap_or_default();
+    let is_public = vstatus.privacy_status.as_deref() == Some("public");
+    let upcoming = backup
+        .publish_at
+        .as_deref()
+        .and_then(|p| DateTime::parse_from_rfc3339(p).ok())
+        .map_or(false, |p| p.with_timezone(&Utc) > now);
+    if backup.publish_at.is_none() || (upcoming && !is_public) {
+        vstatus.privacy_status = backup.privacy_status.clone();
+        vstatus.publish_at = backup.publish_at.clone();
+    }
+    vstatus.license = backup.license.clone();
+    vstatus.embeddable = backup.embeddable;
+    vstatus.public_stats_viewable = backup.public_stats_viewable;
+    vstatus.self_declared_made_for_kids =
+        backup.self_declared_made_for_kids.or(backup.made_for_kids);
+    vstatus.contains_synthetic_media = backup.contains_synthetic_media;
+    vstatus
+}
+
+/// current video with the given fields put back from the backup
+pub(crate) fn restore(
+    current: &Video,
+    backup: &Video,
+    fields: &[RestoreField],
+    now: DateTime<Utc>,
+) -> Video {
+    let mut video = current.clone();
+    let old_snip = backup.snippet.clone().unwrap_or_default();
+    let vsnip = video.snippet.get_or_insert_with(Default::default);
+    for field in fields {
+        match field {
+            RestoreField::Title => vsnip.title = old_snip.title.clone(),
+            RestoreField::Description => vsnip.description = old_snip.description.clone(),
+            RestoreField::Tags => vsnip.tags = old_snip.tags.clone(),
+            RestoreField::Category => vsnip.category_id = old_snip.category_id.clone(),
+            RestoreField::Language => {
+                vsnip.default_language = old_snip.default_language.clone();
+                vsnip.default_audio_language = old_snip.default_audio_language.clone();
+            }
+            RestoreField::Status => {
+                if let Some(old_status) = &backup.status {
+                    video.status = Some(restore_status(current.status.as_ref(), old_status, now));
+                }
+            }
+            RestoreField::Localizations => video.localizations = backup.localizations.clone(),
+        }
+    }
+    video
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::youtube::yt;
+
+    fn video(title: &str, description: &str) -> Video {
+        Video {
+            id: Some("abc".to_string()),
+            snippet: Some(yt::VideoSnippet {
+                title: Some(title.to_string()),
+                description: Some(description.to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_restore() {
+        let backup = video("30. Traits", "about traits");
+        let current = video("30. Traits - From Python to Rust", "oops");
+        let restored = restore(&current, &backup, &[RestoreField::Description], Utc::now());
+        let vsnip = restored.snippet.unwrap();
+        assert_eq!(
+            vsnip.title.as_deref(),
+            Some("30. Traits - From Python to Rust")
+        );
+        assert_eq!(vsnip.description.as_deref(), Some("about traits"));
+    }
+
+    fn with_status(mut video: Video, vstatus: yt::VideoStatus) -> Video {
+        video.status = Some(vstatus.into());
+        video
+    }
+
+    #[test]
+    fn test_restore_status() {
+        let now = DateTime::parse_from_rfc3339("2021-10-01T08:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let scheduled = |publish_at: &str| {
+            with_status(
+                video("30. Traits", "about traits"),
+                yt::VideoStatus {
+                    privacy_status: Some("private".to_string()),
+                    publish_at: Some(publish_at.to_string()),
+                    upload_status: Some("uploaded".to_string()),
+                    made_for_kids: Some(false),
+                    license: Some("creativeCommon".to_string()),
+                    ..Default::default()
+                },
+            )
+        };
+        let current = |privacy: &str| {
+            with_status(
+                video("30. Traits", "about traits"),
+                yt::VideoStatus {
+                    privacy_status: Some(privacy.to_string()),
+                    upload_status: Some("processed".to_string()),
+                    made_for_kids: Some(true),
+                    license: Some("youtube".to_string()),
+                    ..Default::default()
+                },
+            )
+        };
+
+        // published in the meantime
+        let backup = scheduled("2021-09-28T08:00:00Z");
+        let restored = restore(&current("public"), &backup, &[RestoreField::Status], now);
+        let vstatus = restored.status.unwrap();
+        assert_eq!(vstatus.privacy_status.as_deref(), Some("public"));
+        assert_eq!(vstatus.publish_at, None);
+        assert_eq!(vstatus.license.as_deref(), Some("creativeCommon"));
+        assert_eq!(vstatus.self_declared_made_for_kids, Some(false));
+        // read-only fields aren't restored
+        assert_eq!(vstatus.upload_status.as_deref(), Some("processed"));
+        assert_eq!(vstatus.made_for_kids, Some(true));
+
+        // still ahead, but the video is public already
+        let backup = scheduled("2021-10-05T08:00:00Z");
+        let restored = restore(&current("public"), &backup, &[RestoreField::Status], now);
+        assert_eq!(restored.status.unwrap().publish_at, None);
+
+        let restored = restore(&current("unlisted"), &backup, &[RestoreField::Status], now);
+        let vstatus = restored.status.unwrap();
+        assert_eq!(vstatus.privacy_status.as_deref(), Some("private"));
+        assert_eq!(vstatus.publish_at.as_deref(), Some("2021-10-05T08:00:00Z"));
+    }
+
+    #[test]
+    fn test_archive_roundtrip() {
+        let archive = Archive::new(
+            "2021-10-01T08:00:00Z".to_string(),
+            "UC123".to_string(),
+            vec![video("30. Traits", "about traits")],
+        );
+        let json = serde_json::to_string(&archive).unwrap();
+        let loaded: Archive = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.version, VERSION);
+        assert!(loaded.video("abc").is_some());
+        assert!(loaded.video("xyz").is_none());
+    }
+}
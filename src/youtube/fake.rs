@@ -0,0 +1,650 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2021 Michael Kefeder
+//! In-memory YouTube channel implementing the API calls of rsytup, for tests without network.
+//! The same channel is served over HTTP by `fake_server`
+
+use super::api::{UploadProgress, YoutubeApi};
+use super::video::{Video, VideoListResponse};
+use super::{common, yt};
+use std::collections::{BTreeMap, VecDeque};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+/// page size of list requests without max_results, same as the API
+const DEFAULT_PAGE_SIZE: usize = 5;
+
+/// chunk size of uploads, small so test files are sent in several chunks
+const UPLOAD_CHUNK_SIZE: u64 = 8;
+
+/// resumable upload of a video
+#[derive(Debug)]
+pub(crate) struct UploadSession {
+    params: yt::VideosInsertParams,
+    video: Video,
+    /// bytes received so far
+    received: u64,
+    /// the inserted video once the upload is complete
+    inserted: Option<Video>,
+}
+
+/// videos, playlists and thumbnails of the channel
+#[derive(Debug, Default)]
+pub(crate) struct Channel {
+    pub id: String,
+    pub title: String,
+    /// newest first, like the uploads playlist
+    pub videos: Vec<Video>,
+    /// video ids of each playlist
+    pub playlists: BTreeMap<String, Vec<String>>,
+    /// bytes of the thumbnail set per video
+    pub thumbnails: BTreeMap<String, usize>,
+    /// bytes of the uploaded file per video
+    pub uploads: BTreeMap<String, usize>,
+    /// caption tracks of all videos and the bytes of their files, in upload order
+    pub captions: Vec<(yt::Caption, usize)>,
+    /// upload sessions, `session<n>` is the n-th one
+    pub sessions: Vec<UploadSession>,
+    /// status code and reason of error responses returned by the next requests
+    pub failures: VecDeque<(u16, String)>,
+    /// request prefix, status code and reason of error responses of particular requests
+    pub failures_on: Vec<(String, u16, String)>,
+    /// requests in order, e.g. `videos.update snippet`
+    pub requests: Vec<String>,
+}
+
+/// error response as sent by the API
+pub(crate) fn api_error(status: u16, reason: &str, message: &str) -> anyhow::Error {
+    let body = serde_json::json!({
+        "error": {
+            "code": status,
+            "message": message,
+            "errors": [{"reason": reason, "message": message}],
+        }
+    });
+    common::ApiError::HTTPResponseError(
+        hyper::StatusCode::from_u16(status).unwrap_or(hyper::StatusCode::INTERNAL_SERVER_ERROR),
+        body.to_string(),
+    )
+    .into()
+}
+
+/// only the requested parts of a video, the API leaves out the others
+fn with_parts(video: &Video, part: &str) -> Video {
+    let parts: Vec<&str> = part.split(',').map(str::trim).collect();
+    let has = |p: &str| parts.contains(&p);
+    Video {
+        id: video.id.clone(),
+        snippet: video.snippet.clone().filter(|_| has("snippet")),
+        status: video.status.clone().filter(|_| has("status")),
+        localizations: video.localizations.clone().filter(|_| has("localizations")),
+        content_details: video
+            .content_details
+            .clone()
+            .filter(|_| has("contentDetails")),
+        processing_details: video
+            .processing_details
+            .clone()
+            .filter(|_| has("processingDetails")),
+        ..Default::default()
+    }
+}
+
+/// page of `items` starting at the offset in `page_token`
+fn page<T: Clone>(
+    items: &[T],
+    page_token: Option<&str>,
+    max_results: Option<u32>,
+) -> anyhow::Result<(Vec<T>, Option<String>)> {
+    let start = match page_token {
+        Some(token) => token
+            .parse::<usize>()
+            .map_err(|_| api_error(400, "invalidPageToken", "invalid page token"))?,
+        None => 0,
+    };
+    let size = max_results.map_or(DEFAULT_PAGE_SIZE, |m| m.min(50) as usize);
+    let end = (start + size).min(items.len());
+    let next = (end < items.len()).then(|| end.to_string());
+    Ok((items[start.min(end)..end].to_vec(), next))
+}
+
+impl Channel {
+    pub fn uploads_playlist(&self) -> String {
+        format!("UU{}", self.id.trim_start_matches("UC"))
+    }
+
+    fn video_mut(&mut self, video_id: &str) -> Option<&mut Video> {
+        self.videos
+            .iter_mut()
+            .find(|v| v.id.as_deref() == Some(video_id))
+    }
+
+    fn insert(&mut self, video: Video) {
+        let uploads = self.uploads_playlist();
+        let video_id = video.id.clone().unwrap_or_default();
+        self.videos.insert(0, video);
+        self.playlists
+            .entry(uploads)
+            .or_default()
+            .insert(0, video_id);
+    }
+
+    /// records the request and returns a queued failure
+    pub fn request(&mut self, request: String) -> anyhow::Result<()> {
+        let failure_on = self
+            .failures_on
+            .iter()
+            .position(|(prefix, ..)| request.starts_with(prefix.as_str()));
+        self.requests.push(request);
+        if let Some(pos) = failure_on {
+            let (_, status, reason) = self.failures_on.remove(pos);
+            return Err(api_error(status, &reason, "simulated failure"));
+        }
+        match self.failures.pop_front() {
+            Some((status, reason)) => Err(api_error(status, &reason, "simulated failure")),
+            None => Ok(()),
+        }
+    }
+
+    pub fn list_videos(
+        &mut self,
+        params: &yt::VideosListParams,
+    ) -> anyhow::Result<VideoListResponse> {
+        self.request(format!("videos.list {}", params.part))?;
+        let videos: Vec<Video> = match (&params.id, &params.chart) {
+            (Some(_), _) if params.max_results.is_some() => {
+                return Err(api_error(
+                    400,
+                    "incompatibleParameters",
+                    "maxResults isn't supported together with id",
+                ))
+            }
+            // unknown ids are left out, not an error
+            (Some(ids), _) => ids
+                .split(',')
+                .filter_map(|id| self.videos.iter().find(|v| v.id.as_deref() == Some(id)))
+                .map(|v| with_parts(v, &params.part))
+                .collect(),
+            (None, Some(_)) => self
+                .videos
+                .iter()
+                .map(|v| with_parts(v, &params.part))
+                .collect(),
+            (None, None) => return Err(api_error(400, "missingRequiredParameter", "no filter")),
+        };
+        let (items, next_page_token) = page(
+            &videos,
+            params.page_token.as_deref(),
+            params.max_results.or(Some(50)),
+        )?;
+        Ok(VideoListResponse {
+            items: Some(items),
+            next_page_token,
+        })
+    }
+
+    pub fn update_video(
+        &mut self,
+        params: &yt::VideosUpdateParams,
+        video: &Video,
+    ) -> anyhow::Result<Video> {
+        self.request(format!("videos.update {}", params.part))?;
+        let video_id = video.id.as_deref().unwrap_or_default();
+        let stored = self
+            .video_mut(video_id)
+            .ok_or_else(|| api_error(404, "videoNotFound", "video not found"))?;
+        for part in params.part.split(',').map(str::trim) {
+            match part {
+                "id" => {}
+                "snippet" => {
+                    let vsnip = video.snippet.clone().unwrap_or_default();
+                    if vsnip.title.as_deref().unwrap_or_default().is_empty() {
+                        return Err(api_error(400, "invalidTitle", "title is required"));
+                    }
+                    if vsnip.category_id.is_none() {
+                        return Err(api_error(400, "invalidCategoryId", "category is required"));
+                    }
+                    stored.snippet = Some(vsnip);
+                }
+                "status" => {
+                    let mut vstatus = video.status.clone().unwrap_or_default();
+                    let old = stored.status.clone().unwrap_or_default();
+                    // read-only fields are kept
+                    vstatus.made_for_kids = vstatus.self_declared_made_for_kids;
+                    vstatus.upload_status = old.upload_status.clone();
+                    stored.status = Some(vstatus);
+                }
+                "localizations" => {
+                    let vsnip = video.snippet.as_ref().or(stored.snippet.as_ref());
+                    if vsnip.and_then(|s| s.default_language.as_ref()).is_none() {
+                        return Err(api_error(
+                            400,
+                            "defaultLanguageNotSet",
+                            "localizations need snippet.defaultLanguage",
+                        ));
+                    }
+                    stored.localizations = video.localizations.clone();
+                }
+                _ => return Err(api_error(400, "unexpectedPart", "part can't be updated")),
+            }
+        }
+        Ok(with_parts(stored, &params.part))
+    }
+
+    /// starts an upload session, the video is inserted once the whole file was received
+    pub fn start_upload(
+        &mut self,
+        params: &yt::VideosInsertParams,
+        video: &Video,
+    ) -> anyhow::Result<String> {
+        self.request(format!("videos.insert {}", params.part))?;
+        self.sessions.push(UploadSession {
+            params: params.clone(),
+            video: video.clone(),
+            received: 0,
+            inserted: None,
+        });
+        Ok(format!("session{}", self.sessions.len()))
+    }
+
+    fn session(&mut self, session: &str) -> anyhow::Result<&mut UploadSession> {
+        session
+            .strip_prefix("session")
+            .and_then(|n| n.parse::<usize>().ok())
+            .and_then(|n| self.sessions.get_mut(n.checked_sub(1)?))
+            .ok_or_else(|| api_error(404, "notFound", "no such upload session"))
+    }
+
+    fn progress(session: &UploadSession) -> UploadProgress {
+        match &session.inserted {
+            Some(video) => UploadProgress::Done(video.clone()),
+            None => UploadProgress::Received(session.received),
+        }
+    }
+
+    pub fn upload_status(&mut self, session: &str, total: u64) -> anyhow::Result<UploadProgress> {
+        self.request(format!("upload {} bytes */{}", session, total))?;
+        Ok(Self::progress(self.session(session)?))
+    }
+
+    /// appends a chunk, the video is inserted when the upload is complete
+    pub fn upload_chunk(
+        &mut self,
+        session: &str,
+        start: u64,
+        chunk: &[u8],
+        total: u64,
+    ) -> anyhow::Result<UploadProgress> {
+        let end = start + chunk.len() as u64;
+        self.request(format!(
+            "upload {} bytes {}-{}/{}",
+            session,
+            start,
+            end - 1,
+            total
+        ))?;
+        let upload = self.session(session)?;
+        if upload.inserted.is_some() || start != upload.received || end > total {
+            return Err(api_error(
+                400,
+                "badContentRange",
+                "chunk doesn't continue the upload",
+            ));
+        }
+        upload.received = end;
+        if end < total {
+            return Ok(UploadProgress::Received(end));
+        }
+        let (params, video) = (upload.params.clone(), upload.video.clone());
+        let inserted = self.insert_video(&params, &video, total as usize);
+        let upload = self.session(session)?;
+        upload.inserted = Some(inserted);
+        Ok(Self::progress(upload))
+    }
+
+    /// stores a video of which `size` bytes were uploaded
+    pub fn insert_video(
+        &mut self,
+        params: &yt::VideosInsertParams,
+        video: &Video,
+        size: usize,
+    ) -> Video {
+        let video_id = format!("fake{:07}", self.videos.len() + 1);
+        let mut video = video.clone();
+        video.id = Some(video_id.clone());
+        let vstatus = video.status.get_or_insert_with(Default::default);
+        vstatus.upload_status = Some("uploaded".to_string());
+        vstatus.made_for_kids = vstatus.self_declared_made_for_kids;
+        video.processing_details = Some(yt::VideoProcessingDetails {
+            processing_status: Some("succeeded".to_string()),
+            ..Default::default()
+        });
+        self.insert(video.clone());
+        self.uploads.insert(video_id, size);
+        with_parts(&video, &params.part)
+    }
+
+    pub fn set_thumbnail(
+        &mut self,
+        params: &yt::ThumbnailsSetParams,
+        size: usize,
+    ) -> anyhow::Result<yt::ThumbnailSetResponse> {
+        if self.video_mut(&params.video_id).is_none() {
+            return Err(api_error(404, "videoNotFound", "video not found"));
+        }
+        self.thumbnails.insert(params.video_id.clone(), size);
+        Ok(yt::ThumbnailSetResponse {
+            kind: Some("youtube#thumbnailSetResponse".to_string()),
+            ..Default::default()
+        })
+    }
+
+    pub fn list_playlist_items(
+        &mut self,
+        params: &yt::PlaylistItemsListParams,
+    ) -> anyhow::Result<yt::PlaylistItemListResponse> {
+        self.request(format!("playlistItems.list {}", params.part))?;
+        let playlist_id = params.playlist_id.clone().unwrap_or_default();
+        let ids = self
+            .playlists
+            .get(&playlist_id)
+            .ok_or_else(|| api_error(404, "playlistNotFound", "playlist not found"))?;
+        let (ids, next_page_token) = page(ids, params.page_token.as_deref(), params.max_results)?;
+        let items = ids
+            .iter()
+            .map(|id| {
+                let vsnip = self
+                    .videos
+                    .iter()
+                    .find(|v| v.id.as_deref() == Some(id))
+                    .and_then(|v| v.snippet.clone())
+                    .unwrap_or_default();
+                yt::PlaylistItem {
+                    snippet: Some(yt::PlaylistItemSnippet {
+                        playlist_id: Some(playlist_id.clone()),
+                        title: vsnip.title,
+                        description: vsnip.description,
+                        resource_id: Some(yt::ResourceId {
+                            kind: Some("youtube#video".to_string()),
+                            video_id: Some(id.clone()),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }
+            })
+            .collect();
+        Ok(yt::PlaylistItemListResponse {
+            items: Some(items),
+            next_page_token,
+            ..Default::default()
+        })
+    }
+
+    pub fn insert_playlist_item(
+        &mut self,
+        params: &yt::PlaylistItemsInsertParams,
+        item: &yt::PlaylistItem,
+    ) -> anyhow::Result<yt::PlaylistItem> {
+        self.request(format!("playlistItems.insert {}", params.part))?;
+        let vsnip = item.snippet.clone().unwrap_or_default();
+        let video_id = vsnip
+            .resource_id
+            .as_ref()
+            .and_then(|r| r.video_id.clone())
+            .ok_or_else(|| api_error(400, "invalidResourceId", "resource id is required"))?;
+        if self.video_mut(&video_id).is_none() {
+            return Err(api_error(404, "videoNotFound", "video not found"));
+        }
+        let playlist = self
+            .playlists
+            .get_mut(vsnip.playlist_id.as_deref().unwrap_or_default())
+            .ok_or_else(|| api_error(404, "playlistNotFound", "playlist not found"))?;
+        playlist.push(video_id);
+        Ok(item.clone())
+    }
+
+    pub fn list_channels(
+        &mut self,
+        params: &yt::ChannelsListParams,
+    ) -> anyhow::Result<yt::ChannelListResponse> {
+        self.request(format!("channels.list {}", params.part))?;
+        let items = match params.mine {
+            Some(true) => vec![yt::Channel {
+                id: Some(self.id.clone()),
+                snippet: Some(yt::ChannelSnippet {
+                    title: Some(self.title.clone()),
+                    ..Default::default()
+                }),
+                content_details: Some(yt::ChannelContentDetails {
+                    related_playlists: Some(yt::ChannelContentDetailsRelatedPlaylists {
+                        uploads: Some(self.uploads_playlist()),
+                        ..Default::default()
+                    }),
+                }),
+                ..Default::default()
+            }],
+            _ => vec![],
+        };
+        Ok(yt::ChannelListResponse {
+            items: Some(items),
+            ..Default::default()
+        })
+    }
+
+    /// caption tracks of a video, only served over HTTP as rsytup doesn't manage captions
+    pub fn list_captions(
+        &mut self,
+        params: &yt::CaptionsListParams,
+    ) -> anyhow::Result<yt::CaptionListResponse> {
+        self.request(format!("captions.list {}", params.part))?;
+        if self.video_mut(&params.video_id).is_none() {
+            return Err(api_error(404, "videoNotFound", "video not found"));
+        }
+        let ids: Option<Vec<&str>> = params.id.as_deref().map(|id| id.split(',').collect());
+        let items = self
+            .captions
+            .iter()
+            .map(|(caption, _)| caption)
+            .filter(|c| {
+                let csnip = c.snippet.as_ref();
+                csnip.and_then(|s| s.video_id.as_deref()) == Some(params.video_id.as_str())
+            })
+            .filter(|c| match &ids {
+                Some(ids) => ids.contains(&c.id.as_deref().unwrap_or_default()),
+                None => true,
+            })
+            .cloned()
+            .collect();
+        Ok(yt::CaptionListResponse {
+            items: Some(items),
+            ..Default::default()
+        })
+    }
+
+    /// adds the caption track once its file of `size` bytes is uploaded
+    pub fn insert_caption(
+        &mut self,
+        _params: &yt::CaptionsInsertParams,
+        caption: &yt::Caption,
+        size: usize,
+    ) -> anyhow::Result<yt::Caption> {
+        let mut caption = caption.clone();
+        let csnip = caption.snippet.get_or_insert_with(Default::default);
+        let video_id = csnip.video_id.clone().unwrap_or_default();
+        if self.video_mut(&video_id).is_none() {
+            return Err(api_error(404, "videoNotFound", "video not found"));
+        }
+        csnip.status = Some("serving".to_string());
+        caption.id = Some(format!("caption{}", self.captions.len() + 1));
+        self.captions.push((caption.clone(), size));
+        Ok(caption)
+    }
+}
+
+/// the channel shared by all requests
+pub(crate) struct FakeYoutube {
+    channel: Mutex<Channel>,
+}
+
+impl Default for FakeYoutube {
+    fn default() -> Self {
+        let channel = Channel {
+            id: "UCfake".to_string(),
+            title: "Fake Channel".to_string(),
+            ..Default::default()
+        };
+        let mut playlists = BTreeMap::new();
+        playlists.insert(channel.uploads_playlist(), vec![]);
+        Self {
+            channel: Mutex::new(Channel {
+                playlists,
+                ..channel
+            }),
+        }
+    }
+}
+
+impl FakeYoutube {
+    /// adds a private video to the uploads playlist
+    pub fn add_video(&self, video_id: &str, title: &str, description: &str) {
+        let video = Video {
+            id: Some(video_id.to_string()),
+            snippet: Some(yt::VideoSnippet {
+                title: Some(title.to_string()),
+                description: Some(description.to_string()),
+                category_id: Some("28".to_string()),
+                tags: Some(vec!["rust".to_string()]),
+                ..Default::default()
+            }),
+            status: Some(
+                yt::VideoStatus {
+                    privacy_status: Some("private".to_string()),
+                    upload_status: Some("processed".to_string()),
+                    license: Some("youtube".to_string()),
+                    embeddable: Some(true),
+                    made_for_kids: Some(false),
+                    ..Default::default()
+                }
+                .into(),
+            ),
+            processing_details: Some(yt::VideoProcessingDetails {
+                processing_status: Some("succeeded".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        self.channel().insert(video);
+    }
+
+    pub fn add_playlist(&self, playlist_id: &str) {
+        self.channel()
+            .playlists
+            .insert(playlist_id.to_string(), vec![]);
+    }
+
+    /// the next request fails with this status code and reason
+    pub fn fail_next(&self, status: u16, reason: &str) {
+        self.channel()
+            .failures
+            .push_back((status, reason.to_string()));
+    }
+
+    /// the next request starting with `prefix` fails with this status code and reason
+    #[cfg(test)]
+    pub fn fail_on(&self, prefix: &str, status: u16, reason: &str) {
+        self.channel()
+            .failures_on
+            .push((prefix.to_string(), status, reason.to_string()));
+    }
+
+    #[cfg(test)]
+    pub fn video(&self, video_id: &str) -> Option<Video> {
+        self.channel()
+            .videos
+            .iter()
+            .find(|v| v.id.as_deref() == Some(video_id))
+            .cloned()
+    }
+
+    pub fn channel(&self) -> MutexGuard<'_, Channel> {
+        self.channel.lock().unwrap()
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl YoutubeApi for FakeYoutube {
+    async fn list_videos(
+        &self,
+        params: &yt::VideosListParams,
+    ) -> anyhow::Result<VideoListResponse> {
+        self.channel().list_videos(params)
+    }
+
+    async fn update_video(
+        &self,
+        params: &yt::VideosUpdateParams,
+        video: &Video,
+    ) -> anyhow::Result<Video> {
+        self.channel().update_video(params, video)
+    }
+
+    async fn start_video_upload(
+        &self,
+        params: &yt::VideosInsertParams,
+        video: &Video,
+    ) -> anyhow::Result<String> {
+        self.channel().start_upload(params, video)
+    }
+
+    async fn upload_status(&self, session: &str, total: u64) -> anyhow::Result<UploadProgress> {
+        self.channel().upload_status(session, total)
+    }
+
+    async fn upload_chunk(
+        &self,
+        session: &str,
+        start: u64,
+        chunk: Vec<u8>,
+        total: u64,
+    ) -> anyhow::Result<UploadProgress> {
+        self.channel().upload_chunk(session, start, &chunk, total)
+    }
+
+    fn upload_chunk_size(&self) -> u64 {
+        UPLOAD_CHUNK_SIZE
+    }
+
+    async fn set_thumbnail(
+        &self,
+        params: &yt::ThumbnailsSetParams,
+        image: &Path,
+    ) -> anyhow::Result<yt::ThumbnailSetResponse> {
+        self.channel()
+            .request(format!("thumbnails.set {}", params.video_id))?;
+        let size = tokio::fs::read(image).await?.len();
+        self.channel().set_thumbnail(params, size)
+    }
+
+    async fn list_playlist_items(
+        &self,
+        params: &yt::PlaylistItemsListParams,
+    ) -> anyhow::Result<yt::PlaylistItemListResponse> {
+        self.channel().list_playlist_items(params)
+    }
+
+    async fn insert_playlist_item(
+        &self,
+        params: &yt::PlaylistItemsInsertParams,
+        item: &yt::PlaylistItem,
+    ) -> anyhow::Result<yt::PlaylistItem> {
+        self.channel().insert_playlist_item(params, item)
+    }
+
+    async fn list_channels(
+        &self,
+        params: &yt::ChannelsListParams,
+    ) -> anyhow::Result<yt::ChannelListResponse> {
+        self.channel().list_channels(params)
+    }
+}
@@ -0,0 +1,514 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2021 Michael Kefeder
+//! Local stand-in for the YouTube Data API v3 serving the in-memory channel of `fake`, so the
+//! real HTTP code paths including resumable uploads and retries can be tested with
+//! `--api-base-url` and `--auth-flow token`. Serves the videos, thumbnails, playlistItems,
+//! channels and videoCategories endpoints rsytup uses, and listing and uploading captions.
+
+use super::fake::{api_error, FakeYoutube};
+use super::video::Video;
+use super::{common, yt};
+use hyper::header::{AUTHORIZATION, CONTENT_RANGE, CONTENT_TYPE, HOST, LOCATION, RANGE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+
+/// path of the resumable upload sessions, chunks are sent there without authorization
+const SESSION_PATH: &str = "/upload/session/";
+
+enum Upload {
+    Video {
+        params: yt::VideosInsertParams,
+        video: Video,
+    },
+    Thumbnail(yt::ThumbnailsSetParams),
+    Caption {
+        params: yt::CaptionsInsertParams,
+        caption: yt::Caption,
+    },
+}
+
+/// resumable upload receiving chunks
+struct Session {
+    upload: Upload,
+    data: Vec<u8>,
+}
+
+/// the fake channel and the open upload sessions
+#[derive(Default)]
+pub(crate) struct FakeApi {
+    pub youtube: FakeYoutube,
+    sessions: Mutex<BTreeMap<usize, Session>>,
+}
+
+/// decodes a query string, `+` and percent encoded bytes included
+fn parse_query(query: &str) -> HashMap<String, String> {
+    let decode = |s: &str| {
+        let s = s.replace('+', " ");
+        let bytes = s.as_bytes();
+        let mut out = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            let hex = bytes
+                .get(i + 1..i + 3)
+                .and_then(|h| std::str::from_utf8(h).ok())
+                .and_then(|h| u8::from_str_radix(h, 16).ok());
+            match (bytes[i], hex) {
+                (b'%', Some(byte)) => {
+                    out.push(byte);
+                    i += 3;
+                }
+                (byte, _) => {
+                    out.push(byte);
+                    i += 1;
+                }
+            }
+        }
+        String::from_utf8_lossy(&out).into_owned()
+    };
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(k, v)| (decode(k), decode(v)))
+        .collect()
+}
+
+/// start and total size of `bytes 0-524287/2000000`, no start for the status query
+/// `bytes */2000000`
+fn parse_content_range(range: &str) -> Option<(Option<usize>, usize)> {
+    let (span, total) = range.strip_prefix("bytes ")?.split_once('/')?;
+    let start = match span {
+        "*" => None,
+        span => Some(span.split_once('-')?.0.parse().ok()?),
+    };
+    Some((start, total.parse().ok()?))
+}
+
+fn json<T: serde::Serialize>(value: anyhow::Result<T>) -> anyhow::Result<Response<Body>> {
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(serde_json::to_vec(&value?)?.into())?)
+}
+
+/// API errors are sent as they are, anything else is an internal error
+fn error_response(err: anyhow::Error) -> Response<Body> {
+    let (status, body) = match err.downcast_ref::<common::ApiError>() {
+        Some(common::ApiError::HTTPResponseError(status, body)) => (*status, body.clone()),
+        _ => {
+            let err = api_error(500, "backendError", &err.to_string());
+            return error_response(err);
+        }
+    };
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(body.into())
+        .unwrap_or_default()
+}
+
+fn number(value: Option<&String>) -> anyhow::Result<Option<u32>> {
+    value
+        .map(|v| v.parse())
+        .transpose()
+        .map_err(|_| api_error(400, "invalidParameter", "not a number"))
+}
+
+fn video_categories() -> yt::VideoCategoryListResponse {
+    let category = |id: &str, title: &str| yt::VideoCategory {
+        id: Some(id.to_string()),
+        snippet: Some(yt::VideoCategorySnippet {
+            title: Some(title.to_string()),
+            assignable: Some(true),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    yt::VideoCategoryListResponse {
+        items: Some(vec![
+            category("22", "People & Blogs"),
+            category("27", "Education"),
+            category("28", "Science & Technology"),
+        ]),
+        ..Default::default()
+    }
+}
+
+impl FakeApi {
+    async fn handle(&self, req: Request<Body>) -> Response<Body> {
+        match self.route(req).await {
+            Ok(resp) => resp,
+            Err(e) => error_response(e),
+        }
+    }
+
+    async fn route(&self, req: Request<Body>) -> anyhow::Result<Response<Body>> {
+        let (parts, body) = req.into_parts();
+        let body = hyper::body::to_bytes(body).await?;
+        let path = parts.uri.path();
+        if let Some(session) = path.strip_prefix(SESSION_PATH) {
+            return self.upload_chunk(session, &parts.headers, &body);
+        }
+        let authorized = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .map_or(false, |h| h.starts_with("Bearer "));
+        if !authorized {
+            return Err(api_error(401, "authError", "missing bearer token"));
+        }
+        let query = parse_query(parts.uri.query().unwrap_or_default());
+        let q = |key: &str| query.get(key).cloned();
+        let part = q("part").unwrap_or_default();
+        let parse_body = || {
+            serde_json::from_slice(&body).map_err(|e| api_error(400, "parseError", &e.to_string()))
+        };
+        let youtube = &self.youtube;
+        match (parts.method.as_str(), path) {
+            ("GET", "/youtube/v3/videos") => {
+                json(youtube.channel().list_videos(&yt::VideosListParams {
+                    part,
+                    id: q("id"),
+                    chart: q("chart"),
+                    max_results: number(query.get("maxResults"))?,
+                    page_token: q("pageToken"),
+                    ..Default::default()
+                }))
+            }
+            ("PUT", "/youtube/v3/videos") => json(youtube.channel().update_video(
+                &yt::VideosUpdateParams {
+                    part,
+                    ..Default::default()
+                },
+                &parse_body()?,
+            )),
+            ("POST", "/resumable/upload/youtube/v3/videos") => {
+                youtube
+                    .channel()
+                    .request(format!("videos.insert {}", part))?;
+                let upload = Upload::Video {
+                    params: yt::VideosInsertParams {
+                        part,
+                        ..Default::default()
+                    },
+                    video: parse_body()?,
+                };
+                self.start_session(&parts.headers, upload)
+            }
+            ("POST", "/resumable/upload/youtube/v3/thumbnails/set") => {
+                let video_id = q("videoId").unwrap_or_default();
+                youtube
+                    .channel()
+                    .request(format!("thumbnails.set {}", video_id))?;
+                let upload = Upload::Thumbnail(yt::ThumbnailsSetParams {
+                    video_id,
+                    ..Default::default()
+                });
+                self.start_session(&parts.headers, upload)
+            }
+            ("GET", "/youtube/v3/playlistItems") => json(youtube.channel().list_playlist_items(
+                &yt::PlaylistItemsListParams {
+                    part,
+                    playlist_id: q("playlistId"),
+                    max_results: number(query.get("maxResults"))?,
+                    page_token: q("pageToken"),
+                    ..Default::default()
+                },
+            )),
+            ("POST", "/youtube/v3/playlistItems") => json(youtube.channel().insert_playlist_item(
+                &yt::PlaylistItemsInsertParams {
+                    part,
+                    ..Default::default()
+                },
+                &parse_body()?,
+            )),
+            ("GET", "/youtube/v3/channels") => {
+                json(youtube.channel().list_channels(&yt::ChannelsListParams {
+                    part,
+                    mine: q("mine").map(|mine| mine == "true"),
+                    ..Default::default()
+                }))
+            }
+            ("GET", "/youtube/v3/captions") => {
+                json(youtube.channel().list_captions(&yt::CaptionsListParams {
+                    part,
+                    video_id: q("videoId").unwrap_or_default(),
+                    id: q("id"),
+                    ..Default::default()
+                }))
+            }
+            ("POST", "/resumable/upload/youtube/v3/captions") => {
+                youtube
+                    .channel()
+                    .request(format!("captions.insert {}", part))?;
+                let upload = Upload::Caption {
+                    params: yt::CaptionsInsertParams {
+                        part,
+                        ..Default::default()
+                    },
+                    caption: parse_body()?,
+                };
+                self.start_session(&parts.headers, upload)
+            }
+            ("GET", "/youtube/v3/videoCategories") => {
+                youtube
+                    .channel()
+                    .request(format!("videoCategories.list {}", part))?;
+                json(Ok(video_categories()))
+            }
+            // e.g. POST /fake/fail?status=503&reason=backendError, the next request fails
+            ("POST", "/fake/fail") => {
+                let status = number(query.get("status"))?.unwrap_or(500);
+                let reason = q("reason").unwrap_or_else(|| "backendError".to_string());
+                youtube.fail_next(status as u16, &reason);
+                Ok(Response::builder().status(204).body(Body::empty())?)
+            }
+            (method, path) => Err(api_error(
+                404,
+                "notFound",
+                &format!("{} {} is not served", method, path),
+            )),
+        }
+    }
+
+    /// answers the start of a resumable upload with the location of the session
+    fn start_session(
+        &self,
+        headers: &hyper::HeaderMap,
+        upload: Upload,
+    ) -> anyhow::Result<Response<Body>> {
+        let host = headers
+            .get(HOST)
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("127.0.0.1");
+        let mut sessions = self.sessions.lock().unwrap();
+        let id = sessions.keys().next_back().map_or(1, |id| id + 1);
+        sessions.insert(
+            id,
+            Session {
+                upload,
+                data: vec![],
+            },
+        );
+        Ok(Response::builder()
+            .header(LOCATION, format!("http://{}{}{}", host, SESSION_PATH, id))
+            .header(CONTENT_TYPE, "application/json")
+            .body("{}".into())?)
+    }
+
+    /// appends a chunk, incomplete uploads are answered with 308 and the received range.
+    /// a status query without chunk gets the same answer
+    fn upload_chunk(
+        &self,
+        session: &str,
+        headers: &hyper::HeaderMap,
+        chunk: &[u8],
+    ) -> anyhow::Result<Response<Body>> {
+        let not_found = || api_error(404, "notFound", "no such upload session");
+        let id: usize = session.parse().map_err(|_| not_found())?;
+        let content_range = headers
+            .get(CONTENT_RANGE)
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or_default();
+        // e.g. `upload 1 bytes 0-7/18`, chunks fail like the other requests
+        self.youtube
+            .channel()
+            .request(format!("upload {} {}", id, content_range))?;
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.get_mut(&id).ok_or_else(not_found)?;
+        let (start, total) = parse_content_range(content_range)
+            .ok_or_else(|| api_error(400, "badContentRange", "invalid Content-Range"))?;
+        if let Some(start) = start {
+            if start != session.data.len() {
+                return Err(api_error(
+                    400,
+                    "badContentRange",
+                    "chunk doesn't continue the upload",
+                ));
+            }
+            session.data.extend_from_slice(chunk);
+        }
+        if session.data.len() < total {
+            let mut resp = Response::builder().status(308);
+            // nothing received yet is answered without range
+            if let Some(last) = session.data.len().checked_sub(1) {
+                resp = resp.header(RANGE, format!("bytes=0-{}", last));
+            }
+            return Ok(resp.body(Body::empty())?);
+        }
+        let session = sessions.remove(&id).ok_or_else(not_found)?;
+        drop(sessions);
+        let size = session.data.len();
+        let mut channel = self.youtube.channel();
+        match session.upload {
+            Upload::Video { params, video } => {
+                json(Ok(channel.insert_video(&params, &video, size)))
+            }
+            Upload::Thumbnail(params) => json(channel.set_thumbnail(&params, size)),
+            Upload::Caption { params, caption } => {
+                json(channel.insert_caption(&params, &caption, size))
+            }
+        }
+    }
+}
+
+/// binds to `port` on localhost, 0 picks a free port. The server runs when the future is awaited
+pub(crate) fn bind(
+    api: std::sync::Arc<FakeApi>,
+    port: u16,
+) -> anyhow::Result<(
+    SocketAddr,
+    impl std::future::Future<Output = hyper::Result<()>>,
+)> {
+    let make = make_service_fn(move |_| {
+        let api = api.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let api = api.clone();
+                async move { Ok::<_, Infallible>(api.handle(req).await) }
+            }))
+        }
+    });
+    let server = hyper::Server::try_bind(&SocketAddr::from(([127, 0, 0, 1], port)))?.serve(make);
+    Ok((server.local_addr(), server))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::{AuthFlow, LoginOptions, Scope, TokenBackend, UploadOptions};
+    use crate::youtube::{
+        change_description, upload_file, upload_thumbnail, uploaded_video_list, YoutubeClient,
+    };
+    use clap::Parser;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_parse() {
+        let query = parse_query("&part=id%2Csnippet&id=abc&q=a+b");
+        assert_eq!(query["part"], "id,snippet");
+        assert_eq!(query["id"], "abc");
+        assert_eq!(query["q"], "a b");
+        assert_eq!(
+            parse_content_range("bytes 0-524287/2000000"),
+            Some((Some(0), 2000000))
+        );
+        assert_eq!(
+            parse_content_range("bytes */2000000"),
+            Some((None, 2000000))
+        );
+        assert_eq!(parse_content_range("bytes 0-/x"), None);
+    }
+
+    #[tokio::test]
+    async fn test_http_roundtrip() {
+        let api = Arc::new(FakeApi::default());
+        api.youtube.add_video("abc", "30. Traits", "about traits");
+        let (addr, server) = bind(api.clone(), 0).unwrap();
+        tokio::spawn(server);
+        let login = LoginOptions {
+            auth_flow: AuthFlow::Token,
+            token_storage: TokenBackend::File,
+            token_file: "unused.json".into(),
+            access_token: Some("fake-token".to_string()),
+            api_base_url: Some(format!("http://{}/", addr)),
+        };
+        let client = YoutubeClient::new(&login, &[Scope::ForceSsl])
+            .await
+            .unwrap();
+
+        // more than the 5 MiB chunk size of resumable uploads
+        let size = 6 * 1024 * 1024 + 1;
+        let dir = std::env::temp_dir();
+        let video_fn = dir.join(format!("rsytup-{}-http.mp4", std::process::id()));
+        std::fs::write(&video_fn, vec![0u8; size]).unwrap();
+        let thumb = dir.join(format!("rsytup-{}-http.png", std::process::id()));
+        std::fs::write(&thumb, b"not really a png").unwrap();
+        let options = UploadOptions::try_parse_from([
+            "upload",
+            "--file",
+            video_fn.to_str().unwrap(),
+            "--description",
+            "about lifetimes",
+            "--title",
+            "31. Lifetimes",
+            "--publish-at",
+            "asap=",
+        ])
+        .unwrap();
+        // starting the session fails once and is retried
+        api.youtube.fail_next(503, "backendError");
+        // so does the second chunk, the upload resumes after the first one
+        let second_chunk = "upload 1 bytes 5242880-6291456/6291457";
+        api.youtube.fail_on(second_chunk, 503, "backendError");
+        let video_id = upload_file(&client, &options, &video_fn).await.unwrap();
+        assert_eq!(api.youtube.channel().uploads[&video_id], size);
+        let requests = api.youtube.channel().requests.clone();
+        let count = |r: &str| requests.iter().filter(|req| *req == r).count();
+        assert_eq!(count("upload 1 bytes 0-5242879/6291457"), 1);
+        assert_eq!(count(second_chunk), 2);
+        assert_eq!(count("upload 1 bytes */6291457"), 2);
+        upload_thumbnail(&client, &video_id, &thumb).await.unwrap();
+        assert_eq!(api.youtube.channel().thumbnails[&video_id], 16);
+
+        change_description(&client, "abc", "\nbye", crate::options::ChangeMode::Append)
+            .await
+            .unwrap();
+        let vsnip = api.youtube.video("abc").unwrap().snippet.unwrap();
+        assert_eq!(vsnip.description.as_deref(), Some("about traits\nbye"));
+
+        let vids = uploaded_video_list(&client).await.unwrap();
+        let ids: Vec<&str> = vids.iter().map(|v| v.id.as_str()).collect();
+        assert_eq!(ids, vec![video_id.as_str(), "abc"]);
+
+        let err = upload_thumbnail(&client, "xyz", &thumb).await.unwrap_err();
+        assert!(format!("{:#}", err).contains("videoNotFound"), "{:#}", err);
+
+        let unauthorized = hyper::Client::new()
+            .get(
+                format!("http://{}/youtube/v3/channels?part=id&mine=true", addr)
+                    .parse()
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(unauthorized.status(), 401);
+
+        // captions aren't managed by rsytup, the endpoints are requested directly
+        let http = hyper::Client::new();
+        let start = Request::post(format!(
+            "http://{}/resumable/upload/youtube/v3/captions?part=snippet",
+            addr
+        ))
+        .header(AUTHORIZATION, "Bearer fake-token")
+        .body(Body::from(
+            r#"{"snippet": {"videoId": "abc", "language": "de", "name": "Deutsch"}}"#,
+        ))
+        .unwrap();
+        let resp = http.request(start).await.unwrap();
+        let session = resp.headers()[LOCATION].to_str().unwrap().to_string();
+        let upload = Request::put(session)
+            .header(CONTENT_RANGE, "bytes 0-9/10")
+            .body(Body::from("1\n0:00 Hi\n"))
+            .unwrap();
+        assert_eq!(http.request(upload).await.unwrap().status(), 200);
+        let list = Request::get(format!(
+            "http://{}/youtube/v3/captions?part=snippet&videoId=abc",
+            addr
+        ))
+        .header(AUTHORIZATION, "Bearer fake-token")
+        .body(Body::empty())
+        .unwrap();
+        let body = hyper::body::to_bytes(http.request(list).await.unwrap().into_body())
+            .await
+            .unwrap();
+        let captions: yt::CaptionListResponse = serde_json::from_slice(&body).unwrap();
+        let csnip = captions.items.unwrap()[0].snippet.clone().unwrap();
+        assert_eq!(csnip.language.as_deref(), Some("de"));
+        assert_eq!(api.youtube.channel().captions[0].1, 10);
+        std::fs::remove_file(video_fn).unwrap();
+        std::fs::remove_file(thumb).unwrap();
+    }
+}
@@ -0,0 +1,254 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2021 Michael Kefeder
+//! Changing and comparing metadata of existing videos
+
+use super::video::Video;
+use crate::error::Error;
+use crate::options::{MetadataOptions, PrivacyStates};
+use serde_json::Value;
+
+/// a changed field of a video, `field` is the dotted API name e.g. `snippet.title`
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Change {
+    pub field: String,
+    pub old: Value,
+    pub new: Value,
+}
+
+impl Change {
+    /// the video part the field belongs to, e.g. `snippet`
+    pub fn part(&self) -> &str {
+        self.field.split('.').next().unwrap_or_default()
+    }
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {} -> {}", self.field, self.old, self.new)
+    }
+}
+
+/// applies the metadata changes to a video fetched with snippet, status and localizations
+/// `category_id` has to be resolved already, localized descriptions are read from their files
+pub(crate) fn apply(
+    video: &mut Video,
+    options: &MetadataOptions,
+    category_id: Option<&str>,
+) -> anyhow::Result<()> {
+    let vsnip = video.snippet.get_or_insert_with(Default::default);
+    if let Some(title) = &options.title {
+        vsnip.title = Some(title.clone());
+    }
+    if options.tags.is_some() || !options.add_tags.is_empty() || !options.remove_tags.is_empty() {
+        vsnip.tags = Some(options.edit_tags(vsnip.tags.as_deref().unwrap_or_default()));
+    }
+    if let Some(category_id) = category_id {
+        vsnip.category_id = Some(category_id.to_string());
+    }
+    if let Some(language) = &options.language {
+        vsnip.default_language = Some(language.clone());
+        vsnip.default_audio_language = Some(language.clone());
+    }
+    let vstatus = video.status.get_or_insert_with(Default::default);
+    if let Some(privacy) = options.privacy_status {
+        vstatus.privacy_status = Some(privacy.to_string());
+        // the API rejects a publish date on videos which aren't private
+        if privacy != PrivacyStates::Private {
+            vstatus.publish_at = None;
+        }
+    }
+    if let Some(publish_at) = options.publish_datetime()? {
+        // only private videos can have a publish date
+        if publish_at.is_some() && options.privacy_status.is_none() {
+            vstatus.privacy_status = Some("private".to_string());
+        }
+        vstatus.publish_at = publish_at;
+    }
+    let localized =
+        !options.localized_title.is_empty() || !options.localized_description.is_empty();
+    if localized && default_language(video).is_none() {
+        return Err(Error::Config(
+            "localizations need the default language of the video, pass --language".to_string(),
+        )
+        .into());
+    }
+    let localizations = video.localizations.get_or_insert_with(Default::default);
+    for (language, title) in &options.localized_title {
+        localizations.entry(language.clone()).or_default().title = Some(title.clone());
+    }
+    for (language, file) in &options.localized_description {
+        localizations
+            .entry(language.clone())
+            .or_default()
+            .description = Some(std::fs::read_to_string(file)?);
+    }
+    Ok(())
+}
+
+/// snippet.defaultLanguage, required by the API to store localizations
+fn default_language(video: &Video) -> Option<&str> {
+    video.snippet.as_ref()?.default_language.as_deref()
+}
+
+fn diff_values(field: &str, old: &Value, new: &Value, changes: &mut Vec<Change>) {
+    match (old, new) {
+        (Value::Object(old_map), Value::Object(new_map)) => {
+            let mut keys: Vec<&String> = old_map.keys().chain(new_map.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let name = if field.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", field, key)
+                };
+                diff_values(
+                    &name,
+                    old_map.get(key).unwrap_or(&Value::Null),
+                    new_map.get(key).unwrap_or(&Value::Null),
+                    changes,
+                );
+            }
+        }
+        (Value::Object(old_map), Value::Null) => diff_values(
+            field,
+            &Value::Object(old_map.clone()),
+            &Value::Object(Default::default()),
+            changes,
+        ),
+        (Value::Null, Value::Object(new_map)) => diff_values(
+            field,
+            &Value::Object(Default::default()),
+            &Value::Object(new_map.clone()),
+            changes,
+        ),
+        _ if old != new => changes.push(Change {
+            field: field.to_string(),
+            old: old.clone(),
+            new: new.clone(),
+        }),
+        _ => {}
+    }
+}
+
+/// field level differences between two versions of a video
+pub(crate) fn diff(old: &Video, new: &Video) -> anyhow::Result<Vec<Change>> {
+    let mut changes = vec![];
+    diff_values(
+        "",
+        &serde_json::to_value(old)?,
+        &serde_json::to_value(new)?,
+        &mut changes,
+    );
+    Ok(changes)
+}
+
+/// comma separated video parts touched by the changes, as needed by `VideosService::update`.
+/// localizations are only accepted together with the snippet holding the default language
+pub(crate) fn parts(changes: &[Change]) -> String {
+    let mut parts: Vec<&str> = changes.iter().map(Change::part).collect();
+    if parts.contains(&"localizations") {
+        parts.push("snippet");
+    }
+    parts.sort_unstable();
+    parts.dedup();
+    parts.join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::youtube::yt;
+
+    fn video(title: &str, tags: &[&str]) -> Video {
+        Video {
+            id: Some("abc".to_string()),
+            snippet: Some(yt::VideoSnippet {
+                title: Some(title.to_string()),
+                tags: Some(tags.iter().map(|t| t.to_string()).collect()),
+                ..Default::default()
+            }),
+            status: Some(
+                yt::VideoStatus {
+                    privacy_status: Some("public".to_string()),
+                    ..Default::default()
+                }
+                .into(),
+            ),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = video("30. Traits", &["rust"]);
+        assert!(diff(&old, &old).unwrap().is_empty());
+        let mut new = video("30. Traits - From Python to Rust", &["rust", "python"]);
+        new.status.as_mut().unwrap().publish_at = Some("2021-10-01T08:00:00Z".to_string());
+        let changes = diff(&old, &new).unwrap();
+        let fields: Vec<&str> = changes.iter().map(|c| c.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            vec!["snippet.tags", "snippet.title", "status.publishAt"]
+        );
+        assert_eq!(
+            changes[1].to_string(),
+            r#"snippet.title: "30. Traits" -> "30. Traits - From Python to Rust""#
+        );
+        assert_eq!(changes[2].old, Value::Null);
+        assert_eq!(parts(&changes), "snippet,status");
+    }
+
+    #[test]
+    fn test_apply_privacy() {
+        #[derive(clap::Parser)]
+        struct Args {
+            #[clap(flatten)]
+            metadata: MetadataOptions,
+        }
+        let options = |args: &[&str]| {
+            let args = std::iter::once("update").chain(args.iter().copied());
+            <Args as clap::Parser>::try_parse_from(args)
+                .unwrap()
+                .metadata
+        };
+        let mut scheduled = video("30. Traits", &[]);
+        let vstatus = scheduled.status.as_mut().unwrap();
+        vstatus.privacy_status = Some("private".to_string());
+        vstatus.publish_at = Some("2021-10-01T08:00:00Z".to_string());
+
+        let mut video = scheduled.clone();
+        apply(&mut video, &options(&["--privacy-status", "public"]), None).unwrap();
+        let vstatus = video.status.unwrap();
+        assert_eq!(vstatus.privacy_status.as_deref(), Some("public"));
+        assert_eq!(vstatus.publish_at, None);
+
+        let mut video = scheduled.clone();
+        let args = ["--publish-at", "2021-10-08T08:00:00", "--timezone", "UTC"];
+        apply(&mut video, &options(&args), None).unwrap();
+        let vstatus = video.status.unwrap();
+        assert_eq!(vstatus.privacy_status.as_deref(), Some("private"));
+        assert_eq!(vstatus.publish_at.as_deref(), Some("2021-10-08T08:00:00Z"));
+    }
+
+    #[test]
+    fn test_diff_localizations() {
+        let old = video("30. Traits", &[]);
+        let mut new = old.clone();
+        new.localizations = Some(
+            [(
+                "de".to_string(),
+                yt::VideoLocalization {
+                    title: Some("30. Traits auf Deutsch".to_string()),
+                    ..Default::default()
+                },
+            )]
+            .into_iter()
+            .collect(),
+        );
+        let changes = diff(&old, &new).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].field, "localizations.de.title");
+        assert_eq!(parts(&changes), "localizations,snippet");
+    }
+}
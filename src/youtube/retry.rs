@@ -0,0 +1,219 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2021 Michael Kefeder
+//! Retrying API calls which failed for transient reasons
+
+use super::common;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// how often and how long to wait before an API call is repeated
+#[derive(Debug, Clone)]
+pub(crate) struct RetryPolicy {
+    /// attempts including the first one
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(64),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// exponential backoff before the next attempt, up to half of it is added as jitter
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or_default();
+        let jitter_ms = backoff.as_millis() as u64 / 2;
+        let jitter = if jitter_ms > 0 {
+            Duration::from_millis(nanos as u64 % jitter_ms)
+        } else {
+            Duration::ZERO
+        };
+        backoff + jitter
+    }
+}
+
+static POLICY: OnceLock<RetryPolicy> = OnceLock::new();
+
+/// sets the policy of all API calls, only the first call has an effect
+pub(crate) fn set_policy(policy: RetryPolicy) {
+    let _ = POLICY.set(policy);
+}
+
+pub(crate) fn policy() -> &'static RetryPolicy {
+    POLICY.get_or_init(RetryPolicy::default)
+}
+
+/// status code, reason and message of a failed API request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ApiFailure {
+    pub status: u16,
+    pub reason: String,
+    pub message: String,
+}
+
+impl ApiFailure {
+    /// parses the JSON error body of the YouTube API
+    pub fn from_body(status: u16, body: &str) -> Self {
+        let json: serde_json::Value = serde_json::from_str(body).unwrap_or_default();
+        let error = &json["error"];
+        Self {
+            status,
+            reason: error["errors"][0]["reason"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            message: error["message"].as_str().unwrap_or(body).to_string(),
+        }
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        self.status >= 500
+            || matches!(
+                self.reason.as_str(),
+                "rateLimitExceeded" | "userRateLimitExceeded" | "backendError"
+            )
+    }
+}
+
+impl std::fmt::Display for ApiFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HTTP {}", self.status)?;
+        if !self.reason.is_empty() {
+            write!(f, " {}", self.reason)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// the failed API request of an error, if it was one
+pub(crate) fn api_failure(err: &anyhow::Error) -> Option<ApiFailure> {
+    err.chain()
+        .find_map(|e| match e.downcast_ref::<common::ApiError>() {
+            Some(common::ApiError::HTTPResponseError(status, body)) => {
+                Some(ApiFailure::from_body(status.as_u16(), body))
+            }
+            _ => None,
+        })
+}
+
+/// true for connections closed or reset by the server
+fn is_connection_error(err: &anyhow::Error) -> bool {
+    err.chain().any(|e| {
+        if let Some(e) = e.downcast_ref::<std::io::Error>() {
+            use std::io::ErrorKind::*;
+            matches!(
+                e.kind(),
+                ConnectionReset | ConnectionAborted | BrokenPipe | TimedOut | UnexpectedEof
+            )
+        } else if let Some(e) = e.downcast_ref::<hyper::Error>() {
+            e.is_incomplete_message() || e.is_closed() || e.is_timeout()
+        } else {
+            false
+        }
+    })
+}
+
+/// true if repeating the request may succeed
+pub(crate) fn is_retryable(err: &anyhow::Error) -> bool {
+    match api_failure(err) {
+        Some(failure) => failure.is_retryable(),
+        None => is_connection_error(err),
+    }
+}
+
+/// adds the API failure or the operation to the error message
+pub(crate) fn describe(err: anyhow::Error, what: impl std::fmt::Display) -> anyhow::Error {
+    match api_failure(&err) {
+        Some(failure) => err.context(format!("{} failed, {}", what, failure)),
+        None => err.context(format!("{} failed", what)),
+    }
+}
+
+/// runs an API call, it is accounted for in the quota and repeated according to the retry
+/// policy if it failed for a transient reason. The call expression is evaluated for every
+/// attempt, e.g. `retry!(Operation::List, cl.list(&params))`
+macro_rules! retry {
+    ($op:expr, $call:expr) => {{
+        let policy = $crate::youtube::retry::policy();
+        let mut attempt = 1;
+        loop {
+            $crate::quota::spend($op);
+            match $call.await {
+                Ok(value) => break Ok(value),
+                Err(e) => {
+                    let e = anyhow::Error::from(e);
+                    if attempt < policy.max_attempts && $crate::youtube::retry::is_retryable(&e) {
+                        let delay = policy.delay(attempt);
+                        eprintln!(
+                            "{} failed (attempt {}/{}), retrying in {:.1}s: {}",
+                            $op,
+                            attempt,
+                            policy.max_attempts,
+                            delay.as_secs_f32(),
+                            e
+                        );
+                        tokio::time::sleep(delay).await;
+                        attempt += 1;
+                    } else {
+                        break Err($crate::youtube::retry::describe(e, $op));
+                    }
+                }
+            }
+        }
+    }};
+}
+pub(crate) use retry;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy::default();
+        for attempt in 1..=8 {
+            let backoff = Duration::from_secs(2u64.pow(attempt - 1)).min(policy.max_delay);
+            let delay = policy.delay(attempt);
+            assert!(delay >= backoff, "attempt {}: {:?}", attempt, delay);
+            assert!(
+                delay <= backoff + backoff / 2,
+                "attempt {}: {:?}",
+                attempt,
+                delay
+            );
+        }
+    }
+
+    #[test]
+    fn test_api_failure() {
+        let body = r#"{"error": {"code": 403, "message": "quota used up",
+            "errors": [{"reason": "quotaExceeded", "domain": "youtube.quota"}]}}"#;
+        let failure = ApiFailure::from_body(403, body);
+        assert_eq!(failure.reason, "quotaExceeded");
+        assert!(!failure.is_retryable());
+        assert_eq!(failure.to_string(), "HTTP 403 quotaExceeded: quota used up");
+        assert!(ApiFailure::from_body(503, "Service Unavailable").is_retryable());
+        let limited = r#"{"error": {"errors": [{"reason": "rateLimitExceeded"}]}}"#;
+        assert!(ApiFailure::from_body(403, limited).is_retryable());
+        let err = anyhow::Error::from(common::ApiError::HTTPResponseError(
+            hyper::StatusCode::INTERNAL_SERVER_ERROR,
+            r#"{"error": {"errors": [{"reason": "backendError"}]}}"#.to_string(),
+        ));
+        assert!(is_retryable(&err));
+        assert!(!is_retryable(&anyhow::anyhow!("video abc not found")));
+    }
+}
@@ -0,0 +1,374 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2021 Michael Kefeder
+//! Cached OAuth2 tokens in a plain or encrypted file, the OS keyring or an environment variable
+
+use super::common::yup_oauth2;
+use crate::error::Error;
+use crate::options::{AuthFlow, LoginOptions, TokenBackend};
+use chacha20poly1305::aead::{rand_core::RngCore, Aead, KeyInit, OsRng};
+use chacha20poly1305::{AeadCore, ChaCha20Poly1305};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use yup_oauth2::storage::{TokenInfo, TokenStorage};
+
+/// environment variable holding the content of a token cache for `--auth-flow env`
+pub(crate) const TOKEN_CACHE_ENV: &str = "RSYTUP_TOKEN_CACHE";
+
+/// environment variable holding the passphrase of the encrypted token cache.
+/// only passphrases are supported, the key is always derived with PBKDF2
+pub(crate) const PASSPHRASE_ENV: &str = "RSYTUP_TOKEN_PASSPHRASE";
+
+/// keyring entry of the tokens
+#[cfg(feature = "keyring")]
+const KEYRING_SERVICE: &str = "rsytup";
+#[cfg(feature = "keyring")]
+const KEYRING_USER: &str = "oauth2-tokens";
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const PBKDF2_ROUNDS: u32 = 210_000;
+
+/// a token and the scopes it was granted for, same layout as the entries of the
+/// `tokencache.json` written by yup-oauth2
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub(crate) struct CachedToken {
+    pub scopes: Vec<String>,
+    pub token: TokenInfo,
+}
+
+const SCOPE_MANAGE: &str = "https://www.googleapis.com/auth/youtube";
+const SCOPE_FORCE_SSL: &str = "https://www.googleapis.com/auth/youtube.force-ssl";
+const SCOPE_UPLOAD: &str = "https://www.googleapis.com/auth/youtube.upload";
+const SCOPE_READONLY: &str = "https://www.googleapis.com/auth/youtube.readonly";
+
+/// true if a token granted for `granted` is accepted by the API calls needing `requested`.
+/// youtube allows everything, force-ssl allows the calls of readonly and upload as well
+pub(crate) fn covers(granted: &str, requested: &str) -> bool {
+    granted == requested
+        || granted == SCOPE_MANAGE
+        || (granted == SCOPE_FORCE_SSL && matches!(requested, SCOPE_UPLOAD | SCOPE_READONLY))
+}
+
+/// true if one of the tokens may read the channel, upload-only tokens may not
+pub(crate) fn can_read(tokens: &[CachedToken]) -> bool {
+    find_token(tokens, &[SCOPE_READONLY]).is_some()
+}
+
+/// finds a token granted for at least the requested scopes, so e.g. a cached force-ssl token
+/// is used for listing instead of asking for a read-only one
+pub(crate) fn find_token(tokens: &[CachedToken], scopes: &[&str]) -> Option<TokenInfo> {
+    tokens
+        .iter()
+        .find(|t| {
+            scopes
+                .iter()
+                .all(|s| t.scopes.iter().any(|ts| covers(ts, s)))
+        })
+        .map(|t| t.token.clone())
+}
+
+/// replaces the token of the same scopes or adds it
+pub(crate) fn store_token(tokens: &mut Vec<CachedToken>, scopes: &[&str], token: TokenInfo) {
+    let mut scopes: Vec<String> = scopes.iter().map(|s| s.to_string()).collect();
+    scopes.sort();
+    match tokens.iter_mut().find(|t| t.scopes == scopes) {
+        Some(cached) => cached.token = token,
+        None => tokens.push(CachedToken { scopes, token }),
+    }
+}
+
+/// where the token cache is read from and written to
+#[derive(Debug, Clone)]
+pub(crate) enum Backend {
+    File(PathBuf),
+    Encrypted {
+        path: PathBuf,
+        passphrase: String,
+    },
+    Keyring,
+    /// read from `RSYTUP_TOKEN_CACHE`, refreshed tokens are only kept in memory.
+    /// meant for CI where the token cache of an `auth login` is stored as secret
+    Env,
+    /// access token without expiry which is valid for all scopes
+    Static(String),
+}
+
+impl std::fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Backend::File(path) => write!(f, "{}", path.display()),
+            Backend::Encrypted { path, .. } => write!(f, "{} (encrypted)", path.display()),
+            Backend::Keyring => write!(f, "the OS keyring"),
+            Backend::Env => write!(f, "{}", TOKEN_CACHE_ENV),
+            Backend::Static(_) => write!(f, "the access token"),
+        }
+    }
+}
+
+impl Backend {
+    pub fn new(options: &LoginOptions) -> anyhow::Result<Self> {
+        match options.auth_flow {
+            AuthFlow::Env => return Ok(Backend::Env),
+            AuthFlow::Token => {
+                let token = options.access_token.clone().ok_or_else(|| {
+                    Error::Config("the token auth flow needs RSYTUP_ACCESS_TOKEN".to_string())
+                })?;
+                return Ok(Backend::Static(token));
+            }
+            _ => {}
+        }
+        Ok(match options.token_storage {
+            TokenBackend::File => Backend::File(options.token_file.clone()),
+            TokenBackend::Encrypted => Backend::Encrypted {
+                path: options.token_file.clone(),
+                passphrase: std::env::var(PASSPHRASE_ENV).map_err(|_| {
+                    Error::Config(format!(
+                        "the encrypted token storage needs {}",
+                        PASSPHRASE_ENV
+                    ))
+                })?,
+            },
+            TokenBackend::Keyring if cfg!(feature = "keyring") => Backend::Keyring,
+            TokenBackend::Keyring => {
+                return Err(Error::Config(
+                    "rsytup was built without the keyring feature".to_string(),
+                )
+                .into())
+            }
+        })
+    }
+
+    /// cached tokens, empty if not logged in
+    pub fn load(&self) -> anyhow::Result<Vec<CachedToken>> {
+        let json = match self {
+            Backend::File(path) => read_optional(path)?,
+            Backend::Encrypted { path, passphrase } => read_optional(path)?
+                .map(|data| decrypt(passphrase, &data))
+                .transpose()
+                .map_err(|e| {
+                    Error::Auth(format!("{} couldn't be decrypted: {}", path.display(), e))
+                })?,
+            Backend::Keyring => keyring_get()?.map(String::into_bytes),
+            Backend::Env => Some(
+                std::env::var(TOKEN_CACHE_ENV)
+                    .map_err(|_| Error::Config(format!("{} is not set", TOKEN_CACHE_ENV)))?
+                    .into_bytes(),
+            ),
+            Backend::Static(token) => {
+                let token = serde_json::from_value(serde_json::json!({
+                    "access_token": token,
+                    "refresh_token": null,
+                    "expires_at": null,
+                }))?;
+                return Ok(vec![CachedToken {
+                    scopes: vec![SCOPE_MANAGE.to_string()],
+                    token,
+                }]);
+            }
+        };
+        match json {
+            Some(json) => serde_json::from_slice(&json)
+                .map_err(|e| anyhow::anyhow!("{} is no valid token cache: {}", self, e)),
+            None => Ok(vec![]),
+        }
+    }
+
+    pub fn save(&self, tokens: &[CachedToken]) -> anyhow::Result<()> {
+        let json = serde_json::to_vec(tokens)?;
+        match self {
+            Backend::File(path) => write_private(path, &json),
+            Backend::Encrypted { path, passphrase } => {
+                write_private(path, &encrypt(passphrase, &json)?)
+            }
+            Backend::Keyring => keyring_set(std::str::from_utf8(&json)?),
+            Backend::Env | Backend::Static(_) => Ok(()),
+        }
+    }
+
+    /// removes the cached tokens, false if there were none
+    pub fn clear(&self) -> anyhow::Result<bool> {
+        match self {
+            Backend::File(path) | Backend::Encrypted { path, .. } => {
+                match std::fs::remove_file(path) {
+                    Ok(()) => Ok(true),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+                    Err(e) => Err(e.into()),
+                }
+            }
+            Backend::Keyring => keyring_delete(),
+            Backend::Env | Backend::Static(_) => Ok(false),
+        }
+    }
+}
+
+fn read_optional(path: &Path) -> anyhow::Result<Option<Vec<u8>>> {
+    match std::fs::read(path) {
+        Ok(data) => Ok(Some(data)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(anyhow::anyhow!(
+            "{} couldn't be read: {}",
+            path.display(),
+            e
+        )),
+    }
+}
+
+/// writes a file only the owner can read
+fn write_private(path: &Path, data: &[u8]) -> anyhow::Result<()> {
+    use std::io::Write;
+    let mut open = std::fs::OpenOptions::new();
+    open.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        open.mode(0o600);
+        // files created by older versions were world readable
+        if path.exists() {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    open.open(path)
+        .and_then(|mut file| file.write_all(data))
+        .map_err(|e| anyhow::anyhow!("{} couldn't be written: {}", path.display(), e))
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> ChaCha20Poly1305 {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<sha2::Sha256>(passphrase.as_bytes(), salt, PBKDF2_ROUNDS, &mut key);
+    ChaCha20Poly1305::new(&key.into())
+}
+
+/// salt, nonce and ChaCha20-Poly1305 ciphertext, the key is derived with PBKDF2
+pub(crate) fn encrypt(passphrase: &str, plaintext: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher(passphrase, &salt)
+        .encrypt(&nonce, plaintext)
+        .map_err(|_| anyhow::anyhow!("encryption failed"))?;
+    Ok([&salt[..], &nonce[..], &ciphertext[..]].concat())
+}
+
+pub(crate) fn decrypt(passphrase: &str, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    if data.len() < SALT_LEN + NONCE_LEN {
+        anyhow::bail!("too short");
+    }
+    let (salt, rest) = data.split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    cipher(passphrase, salt)
+        .decrypt(nonce.into(), ciphertext)
+        .map_err(|_| anyhow::anyhow!("wrong passphrase in {} or corrupted file", PASSPHRASE_ENV))
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_entry() -> anyhow::Result<keyring::Entry> {
+    Ok(keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER)?)
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_get() -> anyhow::Result<Option<String>> {
+    match keyring_entry()?.get_password() {
+        Ok(json) => Ok(Some(json)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_set(json: &str) -> anyhow::Result<()> {
+    Ok(keyring_entry()?.set_password(json)?)
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_delete() -> anyhow::Result<bool> {
+    match keyring_entry()?.delete_password() {
+        Ok(()) => Ok(true),
+        Err(keyring::Error::NoEntry) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(not(feature = "keyring"))]
+fn keyring_get() -> anyhow::Result<Option<String>> {
+    anyhow::bail!("rsytup was built without the keyring feature")
+}
+
+#[cfg(not(feature = "keyring"))]
+fn keyring_set(_json: &str) -> anyhow::Result<()> {
+    anyhow::bail!("rsytup was built without the keyring feature")
+}
+
+#[cfg(not(feature = "keyring"))]
+fn keyring_delete() -> anyhow::Result<bool> {
+    anyhow::bail!("rsytup was built without the keyring feature")
+}
+
+/// token storage of yup-oauth2, every change is written to the backend
+pub(crate) struct CacheStorage {
+    backend: Backend,
+    tokens: Mutex<Vec<CachedToken>>,
+}
+
+impl CacheStorage {
+    pub fn open(backend: Backend) -> anyhow::Result<Self> {
+        Ok(Self {
+            tokens: Mutex::new(backend.load()?),
+            backend,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl TokenStorage for CacheStorage {
+    async fn set(&self, scopes: &[&str], token: TokenInfo) -> anyhow::Result<()> {
+        let mut tokens = self.tokens.lock().unwrap();
+        store_token(&mut tokens, scopes, token);
+        self.backend.save(&tokens)
+    }
+
+    async fn get(&self, scopes: &[&str]) -> Option<TokenInfo> {
+        find_token(&self.tokens.lock().unwrap(), scopes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_token() {
+        let token: TokenInfo = serde_json::from_str(
+            r#"{"access_token": "ya29", "refresh_token": "1//0g", "expires_at": null}"#,
+        )
+        .unwrap();
+        let mut tokens = vec![];
+        let upload = "https://www.googleapis.com/auth/youtube.upload";
+        let force_ssl = "https://www.googleapis.com/auth/youtube.force-ssl";
+        store_token(&mut tokens, &[upload, force_ssl], token.clone());
+        assert_eq!(tokens.len(), 1);
+        assert!(find_token(&tokens, &[force_ssl]).is_some());
+        assert!(find_token(&tokens, &[SCOPE_READONLY]).is_some());
+        assert!(find_token(&tokens, &[SCOPE_MANAGE]).is_none());
+        store_token(&mut tokens, &[force_ssl, upload], token.clone());
+        assert_eq!(tokens.len(), 1);
+
+        // a read-only token doesn't allow uploads, another token is requested
+        let mut tokens = vec![];
+        store_token(&mut tokens, &[SCOPE_READONLY], token);
+        assert!(find_token(&tokens, &[SCOPE_READONLY]).is_some());
+        assert!(find_token(&tokens, &[upload]).is_none());
+        assert!(can_read(&tokens));
+    }
+
+    #[test]
+    fn test_encryption() {
+        let json = br#"[{"scopes": [], "token": {}}]"#;
+        let data = encrypt("correct horse", json).unwrap();
+        assert!(!data.windows(6).any(|w| w == b"scopes"));
+        assert_eq!(decrypt("correct horse", &data).unwrap(), json);
+        assert!(decrypt("battery staple", &data).is_err());
+        assert!(decrypt("correct horse", &data[..20]).is_err());
+        // a new salt and nonce for every write
+        assert_ne!(encrypt("correct horse", json).unwrap(), data);
+    }
+}
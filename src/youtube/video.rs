@@ -0,0 +1,96 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright © 2021 Michael Kefeder
+//! Video resource with the fields the generated `youtube_v3_types` lack.
+//! async-google-apis generated the types from the YouTube Data API v3 discovery document before
+//! `status.containsSyntheticMedia` was added to the API, the field is kept in a wrapper here
+//! instead of editing the generated file. Drop the wrapper once the types are regenerated.
+
+use super::yt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// the parts of a video rsytup reads and writes
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub(crate) struct Video {
+    #[serde(rename = "id")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "snippet")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snippet: Option<yt::VideoSnippet>,
+    #[serde(rename = "contentDetails")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_details: Option<yt::VideoContentDetails>,
+    #[serde(rename = "status")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<VideoStatus>,
+    #[serde(rename = "processingDetails")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub processing_details: Option<yt::VideoProcessingDetails>,
+    #[serde(rename = "localizations")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub localizations: Option<HashMap<String, yt::VideoLocalization>>,
+}
+
+/// `yt::VideoStatus` and the status fields added to the API later
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub(crate) struct VideoStatus {
+    #[serde(flatten)]
+    pub status: yt::VideoStatus,
+    /// Indicates if the video contains altered or synthetic media. @mutable youtube.videos.insert youtube.videos.update
+    #[serde(rename = "containsSyntheticMedia")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contains_synthetic_media: Option<bool>,
+}
+
+impl From<yt::VideoStatus> for VideoStatus {
+    fn from(status: yt::VideoStatus) -> Self {
+        Self {
+            status,
+            contains_synthetic_media: None,
+        }
+    }
+}
+
+impl std::ops::Deref for VideoStatus {
+    type Target = yt::VideoStatus;
+
+    fn deref(&self) -> &yt::VideoStatus {
+        &self.status
+    }
+}
+
+impl std::ops::DerefMut for VideoStatus {
+    fn deref_mut(&mut self) -> &mut yt::VideoStatus {
+        &mut self.status
+    }
+}
+
+/// page of videos of `videos.list`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub(crate) struct VideoListResponse {
+    #[serde(rename = "items")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub items: Option<Vec<Video>>,
+    #[serde(rename = "nextPageToken")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_page_token: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_extension() {
+        let json = r#"{"id": "abc", "status": {"privacyStatus": "private", "containsSyntheticMedia": true}}"#;
+        let video: Video = serde_json::from_str(json).unwrap();
+        let vstatus = video.status.clone().unwrap();
+        assert_eq!(vstatus.privacy_status.as_deref(), Some("private"));
+        assert_eq!(vstatus.contains_synthetic_media, Some(true));
+        assert_eq!(
+            serde_json::to_value(&video).unwrap(),
+            serde_json::from_str::<serde_json::Value>(json).unwrap()
+        );
+    }
+}